@@ -14,6 +14,7 @@ mod alpha {
       aa_rope => PUBLIC; // TODO restrict?
       set_app_addrs => restrict_to: [zero, azero];
       get_app_addrs => PUBLIC;
+      get_dao_addr => PUBLIC;
       prove_alpha => restrict_to: [omega];
       make_azero => restrict_to: [omega];
       set_dao_addr => restrict_to: [zero];
@@ -167,6 +168,10 @@ mod alpha {
       self.app_addrs = new;
     }
 
+    pub fn get_dao_addr(&self) -> ComponentAddress {
+      self.dao_addr
+    }
+
     pub fn set_dao_addr(&mut self, new: ComponentAddress) {
       self.dao_addr = new;
     }
@@ -5,20 +5,31 @@ mod eux {
   enable_method_auth! {
     roles {
       azero => updatable_by: [];
+      guardian => updatable_by: [azero];
     },
     methods {
       to_nothing => restrict_to: [azero];
       first_deposit => restrict_to: [azero];
       start_stop => restrict_to: [azero];
+      trip => restrict_to: [guardian, azero];
+      untrip => restrict_to: [azero];
+      get_breaker => PUBLIC;
+      set_breaker => restrict_to: [azero];
       add_liquidity => PUBLIC;
+      guarded_add_liquidity => PUBLIC;
       in_given_out => PUBLIC;
       in_given_price => PUBLIC;
       remove_liquidity => PUBLIC;
       sim_swap => PUBLIC;
       spot_price => PUBLIC;
       swap => PUBLIC;
+      guarded_swap => PUBLIC;
       zap => PUBLIC;
+      guarded_zap => PUBLIC;
       vault_reserves => PUBLIC;
+      twap => PUBLIC;
+      get_twap_params => PUBLIC;
+      set_twap_params => restrict_to: [azero];
       look_within => PUBLIC;
     }
   }
@@ -29,6 +40,20 @@ mod eux {
     pool: (Vault, Vault),
     pool_lp: (ResourceAddress, Decimal),
     swap_fee: Decimal,
+    // circuit breaker, when tripped liquidity can only be removed
+    tripped: bool,
+    // max spot move of a single swap, past it the swap reverts
+    // and of a spot held off the TWAP, past it the pool trips
+    breaker_move: Decimal,
+    // sum of spot * seconds, and when it was last added to
+    price_cumulative: (Decimal, Instant),
+    // (when, price_cumulative) at the start of the last two windows, older first
+    // the TWAP runs from the older one, so over one to two windows
+    twap_checkpoints: ((Instant, Decimal), (Instant, Decimal)),
+    // minutes, the TWAP pushed to USD and bounding the AA
+    twap_window: i64,
+    // %-expressed max gap between spot and TWAP for the AA to run
+    twap_bound: Decimal,
     stopped: bool 
   }
 
@@ -79,6 +104,18 @@ mod eux {
         pool,
         pool_lp: (lp_ra, dec!(0)),
         swap_fee,
+        tripped: false,
+        // a 20% move in a single swap is a mistake or manipulation, not trading
+        breaker_move: dec!("0.2"),
+        price_cumulative: (dec!(0), Clock::current_time_rounded_to_minutes()),
+        twap_checkpoints: (
+          (Clock::current_time_rounded_to_minutes(), dec!(0)),
+          (Clock::current_time_rounded_to_minutes(), dec!(0))
+        ),
+        // half an hour outlasts any pushing done within a few txs
+        // 10% is half the swap breaker, so a swap can't step past it in one go
+        twap_window: 30i64,
+        twap_bound: dec!("0.1"),
         stopped: true
       }
       .instantiate()
@@ -86,6 +123,8 @@ mod eux {
       .roles(
         roles!(
           azero => rule!(require(power_azero));
+          // until a guardian badge is set
+          guardian => rule!(require(power_azero));
         )
       )
       .metadata(
@@ -156,11 +195,40 @@ mod eux {
       self.stopped = input;
     }
 
+    // the guardian can trip it, only azero can untrip it
+    pub fn trip(&mut self) {
+      self.tripped = true;
+    }
+
+    pub fn untrip(&mut self) {
+      self.tripped = false;
+    }
+
+    pub fn get_breaker(&self) -> (bool, Decimal) {
+      (self.tripped, self.breaker_move)
+    }
+
+    pub fn set_breaker(&mut self, breaker_move: Decimal) {
+      assert!( breaker_move > dec!(0),
+        "breaker move out of bounds");
+      self.breaker_move = breaker_move;
+    }
+
+    // reverts a single swap moving the spot too far
+    // a single swap can't trip the pool, see observe
+    fn check_breaker(&self, before: Decimal, after: Decimal) {
+      assert!( after <= before * (dec!(1) + self.breaker_move) 
+        && after >= before * (dec!(1) - self.breaker_move),
+        "breaker: spot moved too far"); 
+    }
+
     // adds all three, basing it on the REAL deposit for correct proportion
     // does not return excess liquidity, just 'swap-balances' them out
     pub fn add_liquidity(&mut self, mut b1: Bucket, mut b2: Bucket) -> (Bucket, Option<Bucket>) {
-      assert!( !self.stopped && !self.power_eux.is_empty(),
-        "DEX stopped or empty"); 
+      assert!( !self.stopped && !self.tripped && !self.power_eux.is_empty(),
+        "DEX stopped, tripped or empty"); 
+
+      self.observe();
 
       let in1 = b1.amount();
       let in2 = b2.amount();
@@ -212,10 +280,24 @@ mod eux {
       }
     }
 
+    // add_liquidity, panics if minting under min_lp or past the deadline
+    pub fn guarded_add_liquidity(&mut self, b1: Bucket, b2: Bucket, 
+      min_lp: Decimal, deadline: Option<Instant>) -> (Bucket, Option<Bucket>) {
+      Self::check_deadline(deadline);
+
+      let (lp, rem) = self.add_liquidity(b1, b2);
+      assert!( lp.amount() >= min_lp, 
+        "slippage: minted under minimum");
+
+      (lp, rem)
+    }
+
     pub fn remove_liquidity(&mut self, input: Bucket) -> (Bucket, Bucket) {
       assert!( !self.stopped && !self.power_eux.is_empty(),
         "DEX stopped or empty"); 
 
+      self.observe();
+
       assert!( input.resource_address() == self.pool_lp.0,
         "wrong lp resource");
 
@@ -232,24 +314,40 @@ mod eux {
 
     // perform a swap
     fn internal_swap(&mut self, input: Bucket) -> Bucket {
-      let size_in = input.amount() * self.swap_fee;
       let ra_in = input.resource_address();
 
       if ra_in == self.pool.0.resource_address() {
-        let size_out = (size_in * self.pool.1.amount()) 
-          / (size_in + self.pool.0.amount());
+        let size_out = self.out_given_in(input.amount(), 
+          self.pool.0.amount(), self.pool.1.amount());
 
         self.pool.0.put(input);
         self.pool.1.take(size_out)
       } else { // no need to check, will err on wrong ra
-        let size_out = (size_in * self.pool.0.amount()) 
-          / (size_in + self.pool.1.amount());
+        let size_out = self.out_given_in(input.amount(), 
+          self.pool.1.amount(), self.pool.0.amount());
 
         self.pool.1.put(input);
         self.pool.0.take(size_out)
       }
     }
 
+    // x * y = k, with the fee taken out of the input
+    fn out_given_in(&self, input: Decimal, reserves_in: Decimal, reserves_out: Decimal) -> Decimal {
+      let size_in = input * self.swap_fee;
+
+      (size_in * reserves_out) / (size_in + reserves_in)
+    }
+
+    fn eusd(&self) -> Global<AnyComponent> {
+      let alpha: Global<AnyComponent> = self.alpha_addr.into();
+
+      let (eusd_ca, _, _) = 
+        alpha.call_raw::<(ComponentAddress, ComponentAddress, ComponentAddress)>(
+          "get_app_addrs", scrypto_args!()
+        );
+      eusd_ca.into()
+    }
+
     // AA triggers only once per tx, depending on the user direction
     // either letting them buy high / sell low, or being the first to push it down/up
     //
@@ -272,17 +370,17 @@ mod eux {
         return false;
       }
 
-      let alpha: Global<AnyComponent> = self.alpha_addr.into();
+      if self.off_twap() {
+        return false
+      }
 
-      let (eusd_ca, _, _) = 
-        alpha.call_raw::<(ComponentAddress, ComponentAddress, ComponentAddress)>(
-          "get_app_addrs", scrypto_args!()
-        );
-      let eusd: Global<AnyComponent> = eusd_ca.into();
+      let eusd = self.eusd();
 
       // assumes the oracle on USD side was rescaled to EXRD from XRD
-      if let Some((target, oracle, direction)) = eusd.call_raw::<Option<(Decimal, Decimal, bool)>>
-        ("aa_poke", scrypto_args!(self.spot_price())) {
+      let poked = self.power_eux.as_fungible().authorize_with_amount(dec!(1), ||
+        eusd.call_raw::<Option<(Decimal, Decimal, bool)>>("aa_poke", scrypto_args!(self.spot_price()))
+      );
+      if let Some((target, oracle, direction)) = poked {
 
         // is the user trying to swap the same direction that the system wants to?
         let aligned_direction = user_direction == if direction 
@@ -364,8 +462,10 @@ mod eux {
     }
 
     pub fn swap(&mut self, input: Bucket) -> Bucket {
-      assert!( !self.stopped && !self.power_eux.is_empty(),
-        "DEX stopped or empty"); 
+      assert!( !self.stopped && !self.tripped && !self.power_eux.is_empty(),
+        "DEX stopped, tripped or empty"); 
+
+      self.observe();
 
       let direction = input.resource_address();
 
@@ -373,20 +473,39 @@ mod eux {
       let ran = self.perform_aa(direction, true, false);
 
       // swap
+      let before = self.spot_price();
       let ret = self.internal_swap(input);
+      let after = self.spot_price();
 
       // post-swap
       self.perform_aa(direction, false, ran);
 
+      self.check_breaker(before, after);
+
       return ret
     }
 
+    // swap, panics if returning under min_out or past the deadline
+    // for bots composing swaps, where asserting the worktop isn't enough
+    pub fn guarded_swap(&mut self, input: Bucket, 
+      min_out: Decimal, deadline: Option<Instant>) -> Bucket {
+      Self::check_deadline(deadline);
+
+      let ret = self.swap(input);
+      assert!( ret.amount() >= min_out, 
+        "slippage: output under minimum");
+
+      ret
+    }
+
     // EXRD | EUSD -> EUXLP
     // I am well aware that this isn't the exact equation
     // but I am willing to ignore it
     pub fn zap(&mut self, mut input: Bucket) -> Bucket {
-      assert!( !self.stopped && !self.power_eux.is_empty(),
-        "DEX stopped or empty"); 
+      assert!( !self.stopped && !self.tripped && !self.power_eux.is_empty(),
+        "DEX stopped, tripped or empty"); 
+
+      self.observe();
 
       let direction = input.resource_address();
 
@@ -395,7 +514,9 @@ mod eux {
 
       // ghetto zap
       //  if it's good enough for AA, it's good enough for you
+      let before = self.spot_price();
       let p2 = self.internal_swap(input.take(input.amount()/dec!(2)));
+      let after = self.spot_price();
       let (ret, rem) = if input.resource_address() == self.pool.0.resource_address() {
         self.add_liquidity(input, p2)
       } else {
@@ -413,14 +534,42 @@ mod eux {
       // post-swap
       self.perform_aa(direction, false, ran);
 
+      self.check_breaker(before, after);
+
       return ret
     }
 
+    // zap, panics if minting under min_lp or past the deadline
+    pub fn guarded_zap(&mut self, input: Bucket, 
+      min_lp: Decimal, deadline: Option<Instant>) -> Bucket {
+      Self::check_deadline(deadline);
+
+      let ret = self.zap(input);
+      assert!( ret.amount() >= min_lp, 
+        "slippage: minted under minimum");
+
+      ret
+    }
+
+    fn check_deadline(deadline: Option<Instant>) {
+      if let Some(d) = deadline {
+        assert!( Clock::current_time_is_at_or_before(d, TimePrecision::Minute),
+          "deadline passed");
+      }
+    }
+
     // AUXILIARY (for interop)
 
     // how many to input to get a set number on output? 
-    pub fn in_given_out(&self, _output: Decimal, _resource_in: ResourceAddress) { // -> Decimal {
+    // same as sim_swap, includes the fee and the AA ran before the swap
+    pub fn in_given_out(&self, output: Decimal, resource_in: ResourceAddress) -> Decimal {
+      let (reserves_in, reserves_out) = self.sim_reserves(resource_in);
+
+      assert!( output < reserves_out, 
+        "not enough liquidity");
 
+      // out = in*fee*y / (in*fee + x) ~> in = out*x / (fee*(y - out))
+      output * reserves_in / ((reserves_out - output) * self.swap_fee)
     }
 
     // how many to input to push it to target price?
@@ -449,6 +598,98 @@ mod eux {
       return None
     }
 
+    // TWAP parts
+    // uniswap v2 style, the spot is accumulated over time before every change to the reserves
+    // so a price pushed within a tx doesn't count until time passes over it
+
+    // adds the spot since the last change, at most one observation a minute
+    // new observations get the TWAP pushed to USD, as one of its oracle sources
+    // and trip the pool on a spot held past breaker move off the TWAP, the spot here
+    // is the one earlier txs left, so it takes more than one tx to trip it
+    // tripping doesn't stop the swap calling this, reverting would undo the trip
+    fn observe(&mut self) {
+      if self.pool.0.amount() == dec!(0) {
+        return
+      }
+
+      let now = Clock::current_time_rounded_to_minutes();
+      let elapsed = now.seconds_since_unix_epoch 
+        - self.price_cumulative.1.seconds_since_unix_epoch;
+      if elapsed <= 0 {
+        return
+      }
+
+      self.price_cumulative = (
+        self.price_cumulative.0 + self.spot_price() * Decimal::from(elapsed),
+        now
+      );
+
+      // the newer checkpoint becomes the older one once it's a window old
+      let (_, newer) = self.twap_checkpoints;
+      if newer.0.add_minutes(self.twap_window).expect("incoherence")
+          .compare(now, TimeComparisonOperator::Lte) {
+        self.twap_checkpoints = (newer, (now, self.price_cumulative.0));
+      }
+
+      if let Some(twap) = self.twap() {
+        let spot = self.spot_price();
+        if spot > twap * (dec!(1) + self.breaker_move) 
+          || spot < twap * (dec!(1) - self.breaker_move) {
+          self.tripped = true;
+        }
+
+        self.power_eux.as_fungible().authorize_with_amount(dec!(1), ||
+          self.eusd().call_raw::<()>("twap_poke", scrypto_args!(twap))
+        );
+      }
+    }
+
+    // EUSD / EXRD, time weighted over one to two windows (minutes)
+    // None if the older checkpoint isn't a window old yet
+    pub fn twap(&self) -> Option<Decimal> {
+      let now = Clock::current_time_rounded_to_minutes();
+      let (then, cumulative_then) = self.twap_checkpoints.0;
+
+      let elapsed = now.seconds_since_unix_epoch - then.seconds_since_unix_epoch;
+      if elapsed < self.twap_window * 60 {
+        return None
+      }
+
+      let cumulative_now = self.price_cumulative.0 + self.spot_price() 
+        * Decimal::from(now.seconds_since_unix_epoch - self.price_cumulative.1.seconds_since_unix_epoch);
+
+      Some((cumulative_now - cumulative_then) / Decimal::from(elapsed))
+    }
+
+    // no AA off a spot pushed away from the TWAP, it could've been pushed just for it
+    // the cost is that a real move faster than the TWAP turns the AA off as well
+    // until the TWAP catches up, a window or two, with the USD spot gap breaker
+    // still bounding the spot against the oracle in the meantime
+    fn off_twap(&self) -> bool {
+      match self.twap() {
+        Some(twap) => {
+          let spot = self.spot_price();
+          spot > twap * (dec!(1) + self.twap_bound) || spot < twap * (dec!(1) - self.twap_bound)
+        },
+        None => false
+      }
+    }
+
+    // window in minutes, bound %-expressed
+    pub fn get_twap_params(&self) -> (i64, Decimal) {
+      (self.twap_window, self.twap_bound)
+    }
+
+    pub fn set_twap_params(&mut self, window: i64, bound: Decimal) {
+      assert!( window > 0i64, 
+        "nonpositive window");
+      assert!( bound > dec!(0) && bound < dec!(1),
+        "bound out of bounds");
+
+      self.twap_window = window;
+      self.twap_bound = bound;
+    }
+
     // dumps current # of in each bucket
     pub fn vault_reserves(&self) -> (Decimal, Decimal) {
       (self.pool.0.amount(), self.pool.1.amount())
@@ -463,8 +704,58 @@ mod eux {
     }
 
     // simulated swap, returns the amount that will be returned with a regular swap
-    pub fn sim_swap(&self, _input: Decimal, _resource_in: ResourceAddress) { // -> Decimal {
-      
+    pub fn sim_swap(&self, input: Decimal, resource_in: ResourceAddress) -> Decimal {
+      let (reserves_in, reserves_out) = self.sim_reserves(resource_in);
+
+      self.out_given_in(input, reserves_in, reserves_out)
+    }
+
+    // (in, out) reserves as the user's swap will see them
+    // i.e. after the AA that runs before it, if the user is aligned with it
+    // the AA after the swap doesn't change what the user gets, so it's skipped
+    //
+    // net of a performed AA on the pool, profit LP-ing included:
+    // takes in what USD handed out, gives out that repriced at the oracle
+    fn sim_reserves(&self, resource_in: ResourceAddress) -> (Decimal, Decimal) {
+      let is_first = resource_in == self.pool.0.resource_address();
+      assert!( is_first || resource_in == self.pool.1.resource_address(),
+        "wrong resource input");
+
+      let mut reserves = (self.pool.0.amount(), self.pool.1.amount());
+
+      // see perform_aa, no AA off the TWAP
+      if self.off_twap() {
+        return if is_first { reserves } else { (reserves.1, reserves.0) }
+      }
+
+      let eusd = self.eusd();
+
+      // the views of aa_poke and aa_size, a quote can't trip or accrue anything
+      let poked = eusd.call_raw::<Option<(Decimal, Decimal, bool)>>
+        ("aa_peek", scrypto_args!(self.spot_price()));
+      if let Some((target, oracle, direction)) = poked {
+
+        // see perform_aa, pre-swap AA only runs when aligned
+        let aligned_direction = is_first == direction;
+
+        if aligned_direction {
+          if let Some(size) = self.in_given_price(target, direction) {
+            if let Some(available) = eusd.call_raw::<Option<Decimal>>
+              ("aa_size_view", scrypto_args!(size, direction)) {
+
+              if direction {
+                reserves.0 += available;
+                reserves.1 -= oracle * available;
+              } else {
+                reserves.1 += available;
+                reserves.0 -= dec!("1") / oracle * available;
+              }
+            }
+          }
+        }
+      }
+
+      if is_first { reserves } else { (reserves.1, reserves.0) }
     }
   }
 }
\ No newline at end of file
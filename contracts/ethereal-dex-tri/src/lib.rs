@@ -6,18 +6,25 @@ mod tri {
   enable_method_auth! {
     roles {
       azero => updatable_by: [];
+      guardian => updatable_by: [azero];
     },
     methods {
       to_nothing => restrict_to: [azero];
       first_deposit => restrict_to: [azero];
       start_stop => restrict_to: [azero];
+      trip => restrict_to: [guardian, azero];
+      untrip => restrict_to: [azero];
+      get_breaker => PUBLIC;
+      set_breaker => restrict_to: [azero];
       add_liquidity => PUBLIC;
+      guarded_add_liquidity => PUBLIC;
       in_given_out => PUBLIC;
       in_given_price => PUBLIC;
       remove_liquidity => PUBLIC;
       sim_swap => PUBLIC;
       spot_price => PUBLIC;
       swap => PUBLIC;
+      guarded_swap => PUBLIC;
       vault_reserves => PUBLIC;
       look_within => PUBLIC;
     }
@@ -31,6 +38,15 @@ mod tri {
     resources: ((ResourceAddress, Decimal), (ResourceAddress, Decimal)),
     pool: ComponentAddress,
     swap_fee: Decimal,
+    // circuit breaker, when tripped liquidity can only be removed
+    tripped: bool,
+    // max spot move of a single swap, past it the swap reverts
+    // and of a spot held off the anchor, past it the pool trips
+    breaker_move: Decimal,
+    // spot at the start of the window, and when it was taken
+    spot_anchor: (Decimal, Instant),
+    // minutes, how long an anchor lasts
+    breaker_window: i64,
     stopped: bool 
   }
 
@@ -105,6 +121,12 @@ mod tri {
         resources: ((t1, t1w), (t2, t2w)),
         pool: pool.address(),
         swap_fee,
+        tripped: false,
+        // a 20% move in a single swap is a mistake or manipulation, not trading
+        breaker_move: dec!("0.2"),
+        // taken at the first swap
+        spot_anchor: (dec!(0), Instant::new(0i64)),
+        breaker_window: 30i64,
         stopped: true
       }
       .instantiate()
@@ -112,6 +134,8 @@ mod tri {
       .roles(
         roles!(
           azero => rule!(require(power_azero));
+          // until a guardian badge is set
+          guardian => rule!(require(power_azero));
         )
       )
       .metadata(
@@ -180,11 +204,68 @@ mod tri {
     }
     // TODO HALT ALL ACTIONS WHEN STOPPED
 
+    // the guardian can trip it, only azero can untrip it
+    pub fn trip(&mut self) {
+      self.tripped = true;
+    }
+
+    // re-anchored, otherwise the spot that tripped it trips it again
+    pub fn untrip(&mut self) {
+      self.tripped = false;
+      self.spot_anchor = (dec!(0), Instant::new(0i64));
+    }
+
+    // tripped, max move, window (minutes)
+    pub fn get_breaker(&self) -> (bool, Decimal, i64) {
+      (self.tripped, self.breaker_move, self.breaker_window)
+    }
+
+    pub fn set_breaker(&mut self, breaker_move: Decimal, window: i64) {
+      assert!( breaker_move > dec!(0),
+        "breaker move out of bounds");
+      assert!( window > 0i64, 
+        "nonpositive window");
+      self.breaker_move = breaker_move;
+      self.breaker_window = window;
+    }
+
+    // reverts a single swap moving the spot too far
+    // a single swap can't trip the pool, see observe
+    fn check_breaker(&self, before: Decimal, after: Decimal) {
+      assert!( after <= before * (dec!(1) + self.breaker_move) 
+        && after >= before * (dec!(1) - self.breaker_move),
+        "breaker: spot moved too far"); 
+    }
+
+    // trips on a spot held past breaker move off the anchor, i.e. left there by 
+    // earlier txs, so it takes more than one tx and the per swap revert can't catch it
+    // the anchor moves every window, so a slow enough drift never trips it
+    fn observe(&mut self) {
+      let now = Clock::current_time_rounded_to_minutes();
+      let spot = self.spot_price();
+
+      if self.spot_anchor.0 == dec!(0) {
+        self.spot_anchor = (spot, now);
+        return
+      }
+
+      if now.compare(self.spot_anchor.1, TimeComparisonOperator::Gt)
+        && (spot > self.spot_anchor.0 * (dec!(1) + self.breaker_move)
+          || spot < self.spot_anchor.0 * (dec!(1) - self.breaker_move)) {
+        self.tripped = true;
+      }
+
+      if self.spot_anchor.1.add_minutes(self.breaker_window).expect("incoherence")
+          .compare(now, TimeComparisonOperator::Lte) {
+        self.spot_anchor = (spot, now);
+      }
+    }
+
     // adds all three, basing it on the REAL deposit for correct proportion
     // does not return excess liquidity, just 'swap-balances' them out
     pub fn add_liquidity(&mut self, b1: Bucket, b2: Bucket) -> (Bucket, Option<Bucket>) {
-      assert!( !self.stopped && !self.power_tri.is_empty(),
-        "DEX stopped or empty"); 
+      assert!( !self.stopped && !self.tripped && !self.power_tri.is_empty(),
+        "DEX stopped, tripped or empty"); 
 
       let mut pool: Global<TwoResourcePool> = self.pool.into();
 
@@ -193,6 +274,18 @@ mod tri {
       )
     }
 
+    // add_liquidity, panics if minting under min_lp or past the deadline
+    pub fn guarded_add_liquidity(&mut self, b1: Bucket, b2: Bucket, 
+      min_lp: Decimal, deadline: Option<Instant>) -> (Bucket, Option<Bucket>) {
+      Self::check_deadline(deadline);
+
+      let (lp, rem) = self.add_liquidity(b1, b2);
+      assert!( lp.amount() >= min_lp, 
+        "slippage: minted under minimum");
+
+      (lp, rem)
+    }
+
     pub fn remove_liquidity(&mut self, input: Bucket) -> (Bucket, Bucket) {
       assert!( !self.stopped && !self.power_tri.is_empty(),
         "DEX stopped or empty");
@@ -202,53 +295,102 @@ mod tri {
       pool.redeem(input)
     }
 
-    // no slippage limit, can set it in the manifest
+    // no slippage limit, can set it in the manifest or use guarded_swap
     pub fn swap(&mut self, input: Bucket) -> Bucket {
-      assert!( !self.stopped && !self.power_tri.is_empty(),
-        "DEX stopped or empty"); 
+      assert!( !self.stopped && !self.tripped && !self.power_tri.is_empty(),
+        "DEX stopped, tripped or empty"); 
+
+      // tripping here doesn't stop this swap, reverting would undo the trip
+      // it's still held to the per swap move, the next ones are stopped
+      self.observe();
 
       let mut pool: Global<TwoResourcePool> = self.pool.into();
 
-      let size_in = input.amount() * self.swap_fee;
-      let ra_in = input.resource_address();
+      let (ra_out, size_out) = self.out_given_in(input.amount(), input.resource_address());
 
-      let reserves = self.vault_reserves();
+      let before = self.spot_price();
+      let ret = self.power_tri.as_fungible().authorize_with_amount(dec!(1), || {
+        pool.protected_deposit(input);
+        pool.protected_withdraw(ra_out, size_out, 
+          WithdrawStrategy::Rounded(RoundingMode::ToZero))
+      });
+      let after = self.spot_price();
+      self.check_breaker(before, after);
 
-      let (ra_out, w_out) = if ra_in == self.resources.0.0 {
-        self.resources.1
-      } else if ra_in == self.resources.1.0 {
-        self.resources.0
-      } else {
-        panic!("wrong resource input")
-      };
+      ret
+    }
 
-      let reserves_out = reserves.get(&ra_out).expect("coherence error");
-      let reserves_in = reserves.get(&ra_in).expect("coherence error");
+    // swap, panics if returning under min_out or past the deadline
+    // for bots composing swaps, where asserting the worktop isn't enough
+    pub fn guarded_swap(&mut self, input: Bucket, 
+      min_out: Decimal, deadline: Option<Instant>) -> Bucket {
+      Self::check_deadline(deadline);
 
-      let size_out = 
-        *reserves_out * (dec!("1") - 
-          (*reserves_in / (*reserves_in + size_in))
-            .pow((dec!("1") - w_out) / w_out).expect("power incoherence") 
-        );
+      let ret = self.swap(input);
+      assert!( ret.amount() >= min_out, 
+        "slippage: output under minimum");
 
-      self.power_tri.as_fungible().authorize_with_amount(dec!(1), || {
-        pool.protected_deposit(input);
-        pool.protected_withdraw(ra_out, size_out, 
-          WithdrawStrategy::Rounded(RoundingMode::ToZero))
-      })
+      ret
+    }
+
+    fn check_deadline(deadline: Option<Instant>) {
+      if let Some(d) = deadline {
+        assert!( Clock::current_time_is_at_or_before(d, TimePrecision::Minute),
+          "deadline passed");
+      }
     }
 
     // AUXILIARY (for interop)
 
     // how many to input to get a set number on output? 
-    pub fn in_given_out(&self, _output: Decimal, _resource_in: ResourceAddress) { // -> Decimal {
+    // in = r_in * ((r_out / (r_out - out))^(w_out / w_in) - 1) / fee
+    pub fn in_given_out(&self, output: Decimal, resource_in: ResourceAddress) -> Decimal {
+      let ((_, w_in), (ra_out, w_out)) = self.direction(resource_in);
+
+      let reserves = self.vault_reserves();
+      let reserves_in = *reserves.get(&resource_in).expect("coherence error");
+      let reserves_out = *reserves.get(&ra_out).expect("coherence error");
 
+      assert!( output < reserves_out, 
+        "not enough liquidity");
+
+      reserves_in * (
+        (reserves_out / (reserves_out - output))
+          .pow(w_out / w_in).expect("power incoherence")
+        - dec!("1")
+      ) / self.swap_fee
     }
 
     // how many to input to push it to target price?
-    // returns None, if target < spot
-    pub fn in_given_price(&self, _target: Decimal, _resource_in: ResourceAddress) { // -> Option<Decimal> {
+    // price as in spot_price, selling the first resource pushes it up
+    // and selling the second one pushes it down
+    // returns None, if the target is on the other side of spot
+    //
+    // for the weighted invariant, the price of in moves as
+    // new / old = (r_in_new / r_in)^(1 / w_out) 
+    pub fn in_given_price(&self, target: Decimal, resource_in: ResourceAddress) -> Option<Decimal> {
+      let (_, (_, w_out)) = self.direction(resource_in);
+
+      let spot = self.spot_price();
+      let reserves_in = *self.vault_reserves().get(&resource_in).expect("coherence error");
+
+      // price of in, relative to now
+      let ratio = if resource_in == self.resources.0.0 {
+        if target <= spot {
+          return None
+        }
+        target / spot
+      } else {
+        if target >= spot {
+          return None
+        }
+        spot / target
+      };
 
+      Some(
+        reserves_in * (ratio.pow(w_out).expect("power incoherence") - dec!("1"))
+        / self.swap_fee
+      )
     }
 
     // dumps current # of in each bucket
@@ -272,8 +414,42 @@ mod tri {
     }
 
     // simulated swap, returns the amount that will be returned with a regular swap
-    pub fn sim_swap(&self, _input: Decimal, _resource_in: ResourceAddress) { // -> Decimal {
+    pub fn sim_swap(&self, input: Decimal, resource_in: ResourceAddress) -> Decimal {
+      let (_, size_out) = self.out_given_in(input, resource_in);
+      size_out
+    }
+
+    // ((in, w_in), (out, w_out))
+    fn direction(&self, resource_in: ResourceAddress) 
+      -> ((ResourceAddress, Decimal), (ResourceAddress, Decimal)) {
+      if resource_in == self.resources.0.0 {
+        (self.resources.0, self.resources.1)
+      } else if resource_in == self.resources.1.0 {
+        (self.resources.1, self.resources.0)
+      } else {
+        panic!("wrong resource input")
+      }
+    }
+
+    // weighted constant product, with the fee taken out of the input
+    // out = r_out * (1 - (r_in / (r_in + in))^(w_in / w_out))
+    fn out_given_in(&self, input: Decimal, resource_in: ResourceAddress) 
+      -> (ResourceAddress, Decimal) {
+      let (_, (ra_out, w_out)) = self.direction(resource_in);
+
+      let size_in = input * self.swap_fee;
+
+      let reserves = self.vault_reserves();
+      let reserves_out = reserves.get(&ra_out).expect("coherence error");
+      let reserves_in = reserves.get(&resource_in).expect("coherence error");
+
+      let size_out = 
+        *reserves_out * (dec!("1") - 
+          (*reserves_in / (*reserves_in + size_in))
+            .pow((dec!("1") - w_out) / w_out).expect("power incoherence") 
+        );
 
+      (ra_out, size_out)
     }
   }
 }
\ No newline at end of file
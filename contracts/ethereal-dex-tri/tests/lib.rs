@@ -1,47 +1,228 @@
+use radix_engine::transaction::TransactionReceipt;
 use scrypto::prelude::*;
 use scrypto_unit::*;
 use transaction::builder::ManifestBuilder;
+use transaction::prelude::*;
 
-#[test]
-fn test_hello() {
-    // Setup the environment
-    let mut test_runner = TestRunner::builder().build();
-
-    // Create an account
-    let (public_key, _private_key, account) = test_runner.new_allocated_account();
-
-    // Publish package
-    let package_address = test_runner.compile_and_publish(this_package!());
-
-    // Test the `instantiate_hello` function.
-    let manifest = ManifestBuilder::new()
-        .call_function(
-            package_address,
-            "Hello",
-            "instantiate_hello",
-            manifest_args!(),
-        )
-        .build();
-    let receipt = test_runner.execute_manifest_ignoring_fee(
-        manifest,
-        vec![NonFungibleGlobalId::from_public_key(&public_key)],
-    );
-    println!("{:?}\n", receipt);
-    let component = receipt.expect_commit(true).new_component_addresses()[0];
-
-    // Test the `free_token` method.
-    let manifest = ManifestBuilder::new()
-        .call_method(component, "free_token", manifest_args!())
-        .call_method(
+// equal up to the rounding of the pow
+fn assert_near(a: Decimal, b: Decimal) {
+    assert!(a - b < dec!("0.000001") && b - a < dec!("0.000001"), "{} != {}", a, b);
+}
+
+// a started 90/10 Tri at 900/100, so the spot starts at 1/fee
+struct Env {
+    runner: DefaultTestRunner,
+    public_key: Secp256k1PublicKey,
+    account: ComponentAddress,
+    power_azero: ResourceAddress,
+    tri: ComponentAddress,
+    // 90 weighted, 10 weighted
+    t1: ResourceAddress,
+    t2: ResourceAddress,
+    now_ms: i64,
+}
+
+impl Env {
+    fn new() -> Self {
+        let mut runner = TestRunnerBuilder::new().build();
+        let (public_key, _private_key, account) = runner.new_allocated_account();
+
+        let now_ms = 1_700_000_000_000i64;
+        runner
+            .advance_to_round_at_timestamp(Round::of(1), now_ms)
+            .expect_commit_success();
+
+        let power_azero = runner.create_fungible_resource(dec!(1), 0, account);
+        let power_tri = runner.create_fungible_resource(dec!(1), 0, account);
+        let t1 = runner.create_fungible_resource(dec!(10000), 18, account);
+        let t2 = runner.create_fungible_resource(dec!(10000), 18, account);
+
+        let package_address = runner.compile_and_publish(this_package!());
+
+        let mut env = Self {
+            runner,
+            public_key,
             account,
-            "deposit_batch",
-            manifest_args!(ManifestExpression::EntireWorktop),
-        )
-        .build();
-    let receipt = test_runner.execute_manifest_ignoring_fee(
-        manifest,
-        vec![NonFungibleGlobalId::from_public_key(&public_key)],
-    );
-    println!("{:?}\n", receipt);
-    receipt.expect_commit_success();
+            power_azero,
+            tri: account,
+            t1,
+            t2,
+            now_ms,
+        };
+
+        // alpha and bang aren't reached by the swaps, the account stands in
+        let receipt = env.run(
+            ManifestBuilder::new()
+                .withdraw_from_account(account, power_tri, dec!(1))
+                .take_all_from_worktop(power_tri, "power_tri")
+                .call_function_with_name_lookup(
+                    package_address,
+                    "Tri",
+                    "from_nothing",
+                    |lookup| {
+                        (
+                            account,
+                            power_azero,
+                            lookup.bucket("power_tri"),
+                            t1,
+                            dec!("0.9"),
+                            t2,
+                            dec!("0.1"),
+                            dec!("0.997"),
+                            account,
+                        )
+                    },
+                )
+                .build(),
+        );
+        env.tri = receipt.expect_commit_success().new_component_addresses()[0];
+
+        let manifest = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(account, power_azero, dec!(1))
+            .withdraw_from_account(account, t1, dec!(900))
+            .take_all_from_worktop(t1, "t1")
+            .withdraw_from_account(account, t2, dec!(100))
+            .take_all_from_worktop(t2, "t2")
+            .call_method_with_name_lookup(env.tri, "first_deposit", |lookup| {
+                (lookup.bucket("t1"), lookup.bucket("t2"))
+            })
+            .call_method(env.tri, "start_stop", manifest_args!(false))
+            .deposit_batch(account)
+            .build();
+        env.run(manifest).expect_commit_success();
+
+        env
+    }
+
+    fn run(&mut self, manifest: TransactionManifestV1) -> TransactionReceipt {
+        let receipt = self.runner.execute_manifest_ignoring_fee(
+            manifest,
+            vec![NonFungibleGlobalId::from_public_key(&self.public_key)],
+        );
+        println!("{:?}\n", receipt);
+        receipt
+    }
+
+    fn call(&mut self, method: &str, args: ManifestArgs) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .call_method(self.tri, method, args)
+            .build();
+        self.run(manifest)
+    }
+
+    fn swap(&mut self, resource: ResourceAddress, amount: Decimal) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .withdraw_from_account(self.account, resource, amount)
+            .take_all_from_worktop(resource, "input")
+            .call_method_with_name_lookup(self.tri, "swap", |lookup| {
+                (lookup.bucket("input"),)
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.run(manifest)
+    }
+
+    fn balance(&mut self, resource: ResourceAddress) -> Decimal {
+        self.runner.get_component_balance(self.account, resource)
+    }
+
+    fn spot(&mut self) -> Decimal {
+        self.call("spot_price", manifest_args!())
+            .expect_commit_success()
+            .output(0)
+    }
+}
+
+#[test]
+fn test_quotes_match_the_swap() {
+    let mut env = Env::new();
+    let (t1, t2) = (env.t1, env.t2);
+
+    let quote: Decimal = env
+        .call("sim_swap", manifest_args!(dec!(10), t1))
+        .expect_commit_success()
+        .output(0);
+    let before = env.balance(t2);
+    env.swap(t1, dec!(10)).expect_commit_success();
+    assert_eq!(env.balance(t2) - before, quote);
+
+    // what it takes to get exactly one out, the other way
+    let input: Decimal = env
+        .call("in_given_out", manifest_args!(dec!(1), t2))
+        .expect_commit_success()
+        .output(0);
+    let before = env.balance(t1);
+    env.swap(t2, input).expect_commit_success();
+    assert_near(env.balance(t1) - before, dec!(1));
+
+    env.call("sim_swap", manifest_args!(dec!(1), XRD))
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_in_given_price_lands_on_the_target() {
+    let mut env = Env::new();
+    let (t1, t2) = (env.t1, env.t2);
+    let spot = env.spot();
+
+    // selling the first one only pushes it up, the second one only down
+    let none: Option<Decimal> = env
+        .call("in_given_price", manifest_args!(spot * dec!("0.95"), t1))
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(none, None);
+    let none: Option<Decimal> = env
+        .call("in_given_price", manifest_args!(spot * dec!("1.05"), t2))
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(none, None);
+
+    // the fee stays in the pool, so it lands a hair past the target
+    let target = spot * dec!("1.05");
+    let input: Option<Decimal> = env
+        .call("in_given_price", manifest_args!(target, t1))
+        .expect_commit_success()
+        .output(0);
+    env.swap(t1, input.expect("no input")).expect_commit_success();
+    let spot = env.spot();
+    assert!(spot >= target && spot < target * dec!("1.01"));
+
+    let target = spot * dec!("0.95");
+    let input: Option<Decimal> = env
+        .call("in_given_price", manifest_args!(target, t2))
+        .expect_commit_success()
+        .output(0);
+    env.swap(t2, input.expect("no input")).expect_commit_success();
+    let spot = env.spot();
+    assert!(spot <= target && spot > target * dec!("0.99"));
+}
+
+#[test]
+fn test_guarded_swap_min_out_and_deadline() {
+    let mut env = Env::new();
+    let (account, t1) = (env.account, env.t1);
+
+    let quote: Decimal = env
+        .call("sim_swap", manifest_args!(dec!(10), t1))
+        .expect_commit_success()
+        .output(0);
+    let now = Instant::new(env.now_ms / 1000);
+    let past = now.add_minutes(-2).unwrap();
+    let later = now.add_minutes(2).unwrap();
+
+    let guarded = |env: &mut Env, min_out: Decimal, deadline: Option<Instant>| {
+        let manifest = ManifestBuilder::new()
+            .withdraw_from_account(account, t1, dec!(10))
+            .take_all_from_worktop(t1, "input")
+            .call_method_with_name_lookup(env.tri, "guarded_swap", |lookup| {
+                (lookup.bucket("input"), min_out, deadline)
+            })
+            .deposit_batch(account)
+            .build();
+        env.run(manifest)
+    };
+
+    guarded(&mut env, quote + dec!("0.000001"), None).expect_commit_failure();
+    guarded(&mut env, quote, Some(past)).expect_commit_failure();
+    guarded(&mut env, quote, Some(later)).expect_commit_success();
 }
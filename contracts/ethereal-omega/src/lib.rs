@@ -488,7 +488,7 @@ mod omega {
         Action::EDaoVote(_, _) => (),
 
         // Protocol Param actions
-        Action::EUSDChangeParam(i,_) => assert!( *i < 6u64, "out of bounds" ),
+        Action::EUSDChangeParam(i,_) => assert!( *i < 12u64, "out of bounds" ),
 
         // StartStop
         Action::StartStopAll(_) => (),
@@ -549,7 +549,9 @@ mod omega {
             ("get_app_addrs", scrypto_args!());
 
           let usd: Global<AnyComponent> = u.into();
-          let mut params = usd.call_raw::<(Decimal, Decimal, Decimal, Decimal, Decimal, Decimal)>
+          let mut params = usd.call_raw::
+            <(Decimal, Decimal, Decimal, Decimal, Decimal, Decimal, Decimal, Decimal, Decimal,
+              Decimal, Decimal, Decimal)>
             ("get_params", scrypto_args!());
 
           // :^)
@@ -560,13 +562,23 @@ mod omega {
             3 => params.3 = *new,
             4 => params.4 = *new,
             5 => params.5 = *new,
+            6 => params.6 = *new,
+            7 => params.7 = *new,
+            8 => params.8 = *new,
+            9 => params.9 = *new,
+            10 => params.10 = *new,
+            11 => params.11 = *new,
             _ => panic!()
           }
 
           self.power_omega.as_fungible().authorize_with_amount(dec!(1), || {
             let a0 = alpha.call_raw::<Bucket>("make_azero", scrypto_args!());
             a0.as_fungible().authorize_with_all( ||
-              usd.call_raw::<()>("set_params", scrypto_args!(params))
+              usd.call_raw::<()>("set_params", scrypto_args!(
+                params.0, params.1, params.2, params.3, 
+                params.4, params.5, params.6, params.7, params.8,
+                params.9, params.10, params.11
+              ))
             );
             a0.burn();
           });
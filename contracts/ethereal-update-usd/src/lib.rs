@@ -20,11 +20,15 @@ mod upusd {
     }
 
     // potentially needs better variable naming lmao
+    // oracle_staleness -- minutes, the old one had it hardcoded at 5
+    // oracle_max_deviation -- new policy, the old one had no clamp at all
+    //   it's what governance votes in with the update, and has to be over the new
+    //   breaker's 8% oracle move, else the clamp never lets a trip through, see from_something
     pub fn execute(&mut self, 
       usd_addr: ComponentAddress, alpha_addr: ComponentAddress, new_usd_addr: PackageAddress,
       dapp_def_addr: ComponentAddress, p_eux_addr: ResourceAddress, valid_addr: ComponentAddress,
       oracle1_addr: ResourceAddress, oracle2_addr: ResourceAddress, ecdp_addr: ResourceAddress,
-      eusd_addr: ResourceAddress) {
+      eusd_addr: ResourceAddress, oracle_staleness: i64, oracle_max_deviation: Decimal) {
       // lets of manual setup but it's useful for static verification
       let usd_static = "component_tdx_2_1czptk7mmszu79xq6ed70en4aecpg4yyn5j3dyc2z338qcdplu6zxdt";
       let alpha_static = "component_tdx_2_1crk5paafj5mzqselkvkj0440veh3ys4f0erntnvdx8zva94cqc9wdr";
@@ -91,10 +95,16 @@ mod upusd {
             lower_bound, upper_bound, ff, 
             dapp_def_addr,
             oracle, oracle1_addr, oracle2_addr,
+            oracle_staleness, oracle_max_deviation,
             exrd, xrd, ecdp_addr,
             eusd_addr,
+            // the old one has no SP, so no SP units to keep
+            None::<ResourceAddress>,
             alpt, llpt, lt,
-            ep, mcr, bp, mm
+            ep, mcr, bp, mm,
+            // the old one has no borrow rate, redemptions nor liquidation params
+            dec!(0), dec!("0.005"),
+            dec!(0), dec!("0.01"), dec!(30)
           )
         );
         let new_usd_addr: ComponentAddress = 
@@ -1,12 +1,14 @@
 use scrypto::prelude::*;
+use scrypto_math::*;
 
 // let component: Global<AnyComponent> = Global(ObjectStub::new(ObjectStubHandle::Global(GlobalAddress::from(component_address))));
 // let return_value = component.call_raw::<ZygomebFancyReturnType>("method_name", scrypto_args!(param1));
 
 #[derive(NonFungibleData, ScryptoSbor)]
 pub struct Flash {
-  pub size: Decimal,
-  pub isloan: Option<bool> // None -- mint, Some(true) -- XRD, Some(true) -- EXRD 
+  pub principal: Decimal,
+  pub fee: Decimal,
+  pub isloan: Option<bool> // None -- mint, Some(true) -- EXRD, Some(false) -- XRD
 }
 
 // problem: the LP Decimals could run out of Decimal space
@@ -16,7 +18,82 @@ pub struct Ecdp {
   pub assets_lp: Decimal,
   #[mutable]
   pub liabilities_lp: Decimal
-  // TODO liquidation hook
+  // liquidation hooks are kept by Usd, see ecdp_set_hook
+}
+
+// badge delegating part of an ECDP's management, its rights are kept by Usd
+#[derive(NonFungibleData, ScryptoSbor)]
+pub struct EcdpManager {
+  pub ecdp: NonFungibleLocalId
+}
+
+#[derive(ScryptoSbor, Clone)]
+pub struct EcdpManagerRights {
+  // ecdp_collateralize and ecdp_burn
+  pub repay: bool,
+  // minting and uncollateralizing, as long as the CR stays at or above it
+  pub cr_floor: Option<Decimal>
+}
+
+// keeper-executable rules of an ECDP, see execute_protection
+#[derive(ScryptoSbor, Clone)]
+pub struct ProtectionRule {
+  // (trigger, part) -- under the trigger CR, that %-expressed part of the assets is sold to repay
+  pub repay_under: Option<(Decimal, Decimal)>,
+  // (trigger, target) -- over the trigger CR, EUSD is minted to buy collateral, down to the target CR
+  pub boost_over: Option<(Decimal, Decimal)>
+}
+
+// how an extra collateral is priced, always in XRD per 1 unit
+#[derive(ScryptoSbor, Clone)]
+pub enum PriceSource {
+  // stake unit of a validator, at its redemption value
+  Lsu(ComponentAddress),
+  // any component method taking no args
+  Component(ComponentAddress, String)
+}
+
+// where the XRD/USD price comes from, see guarded_get_oracle
+#[derive(ScryptoSbor, Clone)]
+pub enum OracleSource {
+  // the median of the registered feeders, see set_oracle
+  Feeders,
+  // a pull oracle, any component method taking no args returning (XRD/USD, last update)
+  // called, not caught, so if it panics every call reading the price reverts with it
+  // until governance takes it out, so only point it at components that can't
+  Component(ComponentAddress, String),
+  // the TWAP Eux pushes, see twap_poke, assumes EUSD at $1 so best kept as a check
+  EuxTwap
+}
+
+#[derive(ScryptoSbor)]
+pub struct Collateral {
+  pub vault: Vault,
+  pub source: PriceSource,
+  // %-expressed part of the value counted towards the assets
+  pub factor: Decimal,
+  // max EUSD debt backed by it, pro rata of its share in the assets
+  pub ceiling: Decimal
+}
+
+// what to_leftovers hands over to the next version, see carry_over
+#[derive(ScryptoSbor)]
+pub struct Leftovers {
+  // SP unit resource and units outstanding, EUSD / EXRD / XRD
+  pub sp_units: (ResourceAddress, Decimal),
+  pub sp: (Bucket, Bucket, Bucket),
+  // holdings, source, factor, ceiling
+  pub collaterals: Vec<(Bucket, PriceSource, Decimal, Decimal)>,
+  // ECDP, EXRD, XRD and the isolated (debt, liabilities lp) totals
+  pub isolated: Vec<(NonFungibleLocalId, Bucket, Bucket)>,
+  pub iso_totals: (Decimal, Decimal),
+  // claim NFTs, their (id, epoch, XRD) and XRD being unstaked
+  pub claims: Option<Bucket>,
+  pub unstakes: Vec<(NonFungibleLocalId, Epoch, Decimal)>,
+  pub unstaking_xrd: Decimal,
+  pub bad_debt: Decimal,
+  // close_ecdp deposits, and the component holding their records
+  pub deposits: (Bucket, ComponentAddress)
 }
 
 // events
@@ -35,6 +112,31 @@ struct EcdpLiabilitiesEvent {
   new: Decimal
 }
 
+// holdings of an isolated ECDP after a change
+#[derive(ScryptoSbor, ScryptoEvent)]
+struct EcdpIsolatedAssetsEvent {
+  ecdp: NonFungibleLocalId,
+  exrd: Decimal,
+  xrd: Decimal
+}
+
+// None rights -- revoked
+#[derive(ScryptoSbor, ScryptoEvent)]
+struct EcdpManagerEvent {
+  ecdp: NonFungibleLocalId,
+  manager: NonFungibleLocalId,
+  rights: Option<EcdpManagerRights>
+}
+
+// repay -- collateral sold for EUSD, otherwise EUSD minted for collateral
+// size in USD, before the executor's fee
+#[derive(ScryptoSbor, ScryptoEvent)]
+struct ProtectionEvent {
+  ecdp: NonFungibleLocalId,
+  repay: bool,
+  size: Decimal
+}
+
 // sizes shown in the above events which are also emited
 #[derive(ScryptoSbor, ScryptoEvent)]
 struct EcdpLiquidatedEvent {
@@ -46,6 +148,11 @@ struct NewEcdpEvent {
   ecdp: NonFungibleLocalId
 }
 
+#[derive(ScryptoSbor, ScryptoEvent)]
+struct EcdpClosedEvent {
+  ecdp: NonFungibleLocalId
+}
+
 #[derive(ScryptoSbor, ScryptoEvent)]
 struct FlashEvent {
   size: Decimal,
@@ -59,51 +166,151 @@ struct AAEvent {
   profit: Decimal
 }
 
+#[derive(ScryptoSbor, ScryptoEvent)]
+struct RedemptionEvent {
+  size: Decimal,
+  fee: Decimal
+}
+
+// debt absorbed by the stability pool, and the XRD worth of collateral it got for it
+#[derive(ScryptoSbor, ScryptoEvent)]
+struct StabilityPoolEvent {
+  ecdp: NonFungibleLocalId,
+  burned: Decimal,
+  seized: Decimal
+}
+
 #[derive(ScryptoSbor, ScryptoEvent)]
 struct OracleEvent {
   old: Decimal,
   new: Decimal
 }
 
+// a suspicious update held until another feeder confirms it
+#[derive(ScryptoSbor, ScryptoEvent)]
+struct OracleQuarantineEvent {
+  old: Decimal,
+  pending: Decimal
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+struct BreakerEvent {
+  reason: String
+}
+
 // code
 
 #[blueprint]
-#[events(FlashEvent, NewEcdpEvent, EcdpLiquidatedEvent,
-  EcdpAssetsEvent, EcdpLiabilitiesEvent,
-  OracleEvent, AAEvent)]
+#[events(FlashEvent, NewEcdpEvent, EcdpClosedEvent, EcdpLiquidatedEvent,
+  EcdpAssetsEvent, EcdpLiabilitiesEvent, EcdpIsolatedAssetsEvent, EcdpManagerEvent,
+  ProtectionEvent,
+  OracleEvent, OracleQuarantineEvent, BreakerEvent, AAEvent, RedemptionEvent, StabilityPoolEvent)]
 mod usd {
   enable_method_auth! {
     roles {
       azero => updatable_by: [];
       dex => updatable_by: [azero]; // temporary measure, TODO: alpha + delpoy braider
+      guardian => updatable_by: [azero];
+      keeper => updatable_by: [azero];
     },
     methods {
       to_nothing => restrict_to: [azero]; //todo alpha's power zero
+      to_leftovers => restrict_to: [azero];
+      carry_over => restrict_to: [azero];
       start_stop => restrict_to: [azero];
-      aa_poke => PUBLIC;
+      trip => restrict_to: [guardian, azero];
+      untrip => restrict_to: [azero];
+      check_breaker => PUBLIC;
+      get_breaker => PUBLIC;
+      set_breaker => restrict_to: [azero];
+      aa_poke => restrict_to: [dex];
       aa_woke => restrict_to: [dex];
+      aa_size => PUBLIC;
+      aa_peek => PUBLIC;
+      aa_size_view => PUBLIC;
+      twap_poke => restrict_to: [dex];
       aa_choke => PUBLIC;
       exrdxrd => PUBLIC;
+      set_exrd_max_drop => restrict_to: [azero];
+      unstake_exrd => restrict_to: [keeper, azero];
+      claim_unstaked => PUBLIC;
+      unstaking_look => PUBLIC;
+      get_unstake_buffer => PUBLIC;
+      set_unstake_buffer => restrict_to: [azero];
       flash_loan_start => PUBLIC;
       flash_loan_end => PUBLIC;
       flash_mint_start => PUBLIC;
       flash_mint_end => PUBLIC;
+      flash => PUBLIC;
+      get_flash_max => PUBLIC;
+      set_flash_max => restrict_to: [azero];
       liquidate => PUBLIC;
+      liquidation_notice => PUBLIC;
+      ecdp_set_hook => PUBLIC;
+      ecdp_set_protection => PUBLIC;
+      ecdp_merge => PUBLIC;
+      recovery_mode => PUBLIC;
+      ecdp_split => PUBLIC;
+      get_protection => PUBLIC;
+      execute_protection => PUBLIC;
+      set_protection_fee => restrict_to: [azero];
+      set_hook_grace => restrict_to: [azero];
+      redeem => PUBLIC;
+      sp_deposit => PUBLIC;
+      sp_withdraw => PUBLIC;
+      sp_look => PUBLIC;
       tcr => PUBLIC;
       tcr_au_lu => PUBLIC;
       asset_lp_usd => PUBLIC;
       asset_lp_xrd => PUBLIC;
       liability_lp_usd => PUBLIC;
+      sweep_interest => PUBLIC;
+      get_bad_debt => PUBLIC;
+      repay_bad_debt => PUBLIC;
       open_ecdp => PUBLIC;
+      deposit_of => PUBLIC;
+      close_ecdp => PUBLIC;
+      ecdp_add_manager => PUBLIC;
+      ecdp_set_manager => PUBLIC;
+      ecdp_revoke_manager => PUBLIC;
+      ecdp_revoke_managers => PUBLIC;
+      get_manager => PUBLIC;
+      get_managers => PUBLIC;
+      ecdp_reindex => PUBLIC;
+      ecdp_cr => PUBLIC;
+      riskiest_ecdps => PUBLIC;
       ecdp_burn => PUBLIC;
       ecdp_mint => PUBLIC;
       ecdp_collateralize => PUBLIC;
       ecdp_uncollateralize => PUBLIC;
+      ecdp_uncollateralize_in => PUBLIC;
+      ecdp_uncollateralize_asset => PUBLIC;
+      ecdp_uncollateralize_isolated => PUBLIC;
+      ecdp_isolated => PUBLIC;
+      isolated_ecdps => PUBLIC;
+      iso_look => PUBLIC;
+      get_collaterals => PUBLIC;
+      add_collateral => restrict_to: [azero];
+      set_collateral => restrict_to: [azero];
       set_oracle => PUBLIC;
       get_oracle => PUBLIC;
+      get_oracle_params => PUBLIC;
+      set_oracle_params => restrict_to: [azero];
+      get_oracle_sources => PUBLIC;
+      set_oracle_sources => restrict_to: [azero];
+      add_oracle_feeder => restrict_to: [azero];
+      remove_oracle_feeder => restrict_to: [azero];
       guarded_get_oracle => PUBLIC;
       guarded_get_rescaled_oracle => PUBLIC;
       look_within => PUBLIC;
+      eusd_resource => PUBLIC;
+      dex_addr => PUBLIC;
+      ecdp_look => PUBLIC;
+      proven_ecdp => PUBLIC;
+      ecdp_mint_usd => PUBLIC;
+      ecdp_withdraw_usd => PUBLIC;
+      open_ecdp_and_borrow => PUBLIC;
+      ecdp_burn_and_withdraw => PUBLIC;
       get_params => PUBLIC;
       set_params => restrict_to: [azero];
       first_ecdp => restrict_to: [azero];
@@ -117,6 +324,32 @@ mod usd {
 
     ecdp_resource: ResourceAddress,
 
+    // manager badge id -> (ECDP it manages, what it may do)
+    // kept here as the ECDP data schema is fixed on the live resource
+    manager_resource: ResourceAddress,
+    ecdp_managers: KeyValueStore<NonFungibleLocalId, (NonFungibleLocalId, EcdpManagerRights)>,
+    // ECDP -> its managers, capped so the owner can always revoke them all in one go
+    ecdp_manager_index: KeyValueStore<NonFungibleLocalId, Vec<NonFungibleLocalId>>,
+
+    // registry of ECDPs with debt, bucketed by ~5% steps of assets_lp / liabilities_lp
+    // ECDPs share the lp indexes, so that ratio orders them by CR at any price
+    // (bucket, slot) -> ECDP, slots are dense and unordered within a bucket
+    ecdp_buckets: KeyValueStore<(Decimal, u64), NonFungibleLocalId>,
+    // bucket -> number of slots taken
+    ecdp_bucket_lens: KeyValueStore<Decimal, u64>,
+    // occupied buckets, ascending
+    ecdp_bucket_keys: Vec<Decimal>,
+    // ECDP -> (bucket, slot) it is filed under
+    ecdp_bucket_of: KeyValueStore<NonFungibleLocalId, (Decimal, u64)>,
+
+    // isolated ECDPs, holding their own EXRD and XRD in absolute units
+    // their debt is in a liabilities lp of its own, so no redistribution ever reaches them
+    // they don't count towards the tcr, which is about the pooled ones
+    iso_vaults: KeyValueStore<NonFungibleLocalId, (Vault, Vault)>,
+    iso_list: Vec<NonFungibleLocalId>,
+    iso_liabilities_total: Decimal,
+    iso_liabilities_lp_total: Decimal,
+
     // lp totals
     assets_lp_total: Decimal,
     liabilities_lp_total: Decimal,
@@ -129,6 +362,23 @@ mod usd {
     exrd_vault: Vault,
     xrd_vault: Vault,
     exrd_validator: ComponentAddress,
+    // EXRD/XRD as of the epoch, refreshed on the first accrual in an epoch
+    exrdxrd_cache: (Decimal, Epoch),
+    // %-expressed drop of EXRD/XRD between epochs that trips the breaker
+    exrd_max_drop: Decimal,
+    // the validator's claim NFTs, created on the first unstake
+    claim_vault: Option<Vault>,
+    // (claim NFT, claimable from, XRD) still unclaimed
+    unstakes: Vec<(NonFungibleLocalId, Epoch, Decimal)>,
+    // XRD being unstaked, counted in the assets
+    unstaking_xrd: Decimal,
+    // %-expressed part of the assets unstaking has to leave liquid in the vaults
+    unstake_buffer: Decimal,
+
+    // extra collaterals, other LSUs or anything else governance lets in
+    // pooled into the assets like the two above
+    collaterals: KeyValueStore<ResourceAddress, Collateral>,
+    collateral_list: Vec<ResourceAddress>,
 
     ep: Decimal,
     mcr: Decimal,
@@ -142,18 +392,92 @@ mod usd {
     // at least for now 
     maximum_minted: Decimal,
 
+    // stability fee, per second, accrued lazily into liabilities_total
+    // the accrued part is owed to the treasury until swept
+    borrow_rate: Decimal,
+    last_accrual: Instant,
+    interest_pending: Decimal,
+    // EUSD out there no ECDP owes, isolated debt neither the SP nor its collateral covered
+    // paid off by the interest before any of it is swept, or by anyone through repay_bad_debt
+    bad_debt: Decimal,
+
+    // XRD handed back out of the open_ecdp fee on close_ecdp
+    close_refund: Decimal,
+    // that part of the fees, kept apart from the collateral, and who paid it
+    // ECDPs from before the previous version are looked up there, see deposit_of
+    deposit_vault: Vault,
+    ecdp_deposits: KeyValueStore<NonFungibleLocalId, Decimal>,
+    deposits_from: Option<ComponentAddress>,
+
+    // %-expressed cut of the redeemed collateral left with the redeemed ECDPs
+    redemption_fee: Decimal,
+
+    // liquidation, %-expressed seizure on top of the debt, %-expressed liquidator tip
+    // and the USD value of assets under which an ECDP is liquidatable at any CR
+    liq_penalty: Decimal,
+    liq_reward: Decimal,
+    dust: Decimal,
+
+    // ECDP -> (component, method) called by liquidate before seizing
+    // it gets (ECDP id, CR) and returns EUSD to burn and/or collateral to put into the ECDP
+    ecdp_hooks: KeyValueStore<NonFungibleLocalId, (ComponentAddress, String)>,
+    // ECDP -> when it was first noticed liquidatable
+    // hooks are skipped once the notice is hook_grace minutes old, so they can't block forever
+    ecdp_notices: KeyValueStore<NonFungibleLocalId, Instant>,
+    hook_grace: i64,
+
+    // ECDP -> auto-repay / auto-boost rules anyone can execute
+    ecdp_protection: KeyValueStore<NonFungibleLocalId, ProtectionRule>,
+    // %-expressed discount the executor gets on what they bring
+    protection_fee: Decimal,
+
+    // stability pool, EUSD burned against liquidated debt in exchange for its collateral
+    // units are a pro rata claim on all three vaults
+    sp_units: (ResourceAddress, Decimal),
+    sp_eusd: Vault,
+    sp_exrd: Vault,
+    sp_xrd: Vault,
+    // units withdrawn once power is gone and they can't be burned, dead from then on
+    sp_retired: Option<Vault>,
+
     // flashing variables, allow only one active flashing in a tx
     // this includes any MP invocation, which means flash self sale is impossible
     flash_resource: ResourceAddress,
     fl_active: bool, 
     fm_active: bool,
     flash_fee: Decimal,
+    // max EUSD flash minted, max XRD worth flash loaned
+    flash_max: (Decimal, Decimal),
 
-    // exch rate
+    // exch rate, median of the fresh feeder posts
     oracle: Decimal,
     oracle_timestamp: Instant,
-    oracle1: ResourceAddress,
-    oracle2: ResourceAddress,
+    // feeder badge -> (last posted price, when)
+    oracle_feeds: IndexMap<ResourceAddress, (Decimal, Instant)>,
+    // minutes after which a post (or the median itself) is outdated
+    // i.e. the heartbeat, feeders have to post at least this often for the price to stay live
+    oracle_staleness: i64,
+    // %-expressed max move of the price within one staleness window
+    oracle_max_deviation: Decimal,
+    // price the max deviation is measured from, and the start of its window
+    oracle_anchor: (Decimal, Instant),
+    // %-expressed move past which an update is quarantined, see set_oracle
+    oracle_quarantine: Decimal,
+    // quarantined price, when, the feeder that posted it
+    oracle_pending: Option<(Decimal, Instant, ResourceAddress)>,
+    // absolute floor and ceiling of any post
+    oracle_bounds: (Decimal, Decimal),
+    // fallback chain, the first fresh source is the price
+    oracle_sources: Vec<OracleSource>,
+    // %-expressed max gap between the price and the next fresh source
+    oracle_cross_deviation: Decimal,
+    // EUSD/EXRD TWAP off Eux, when it was pushed
+    eux_twap: (Decimal, Instant),
+
+    // circuit breaker, when tripped only repaying, collateralizing, liquidations and the SP go through
+    tripped: bool,
+    // oracle move per update, spot to oracle gap, TCR floor -- past any of these it trips
+    breaker: (Decimal, Decimal, Decimal),
 
     stopped: bool // TODO should AA be also stopped?
   }
@@ -247,7 +571,7 @@ mod usd {
           minter => rule!(require(power_usd.resource_address()));
           minter_updater => rule!(deny_all);
         ))
-        // burns aren't utilized so just keeping it here for the uhh ability
+        // burned by close_ecdp
         .burn_roles(burn_roles!(
           burner => rule!(require(power_usd.resource_address()));
           burner_updater => rule!(deny_all);
@@ -259,6 +583,9 @@ mod usd {
         .create_with_no_initial_supply()
         .address();
 
+      let sp_resource = Self::sp_unit_resource(power_usd.resource_address(), bang);
+      let manager_resource = Self::manager_resource(power_usd.resource_address(), bang);
+
       let a1 = Self {
         alpha_addr,
 
@@ -266,6 +593,20 @@ mod usd {
 
         ecdp_resource,
 
+        ecdp_buckets: KeyValueStore::new(),
+        ecdp_bucket_lens: KeyValueStore::new(),
+        ecdp_bucket_keys: Vec::new(),
+        ecdp_bucket_of: KeyValueStore::new(),
+
+        manager_resource,
+        ecdp_managers: KeyValueStore::new(),
+        ecdp_manager_index: KeyValueStore::new(),
+
+        iso_vaults: KeyValueStore::new(),
+        iso_list: Vec::new(),
+        iso_liabilities_total: dec!(0),
+        iso_liabilities_lp_total: dec!(0),
+
         assets_lp_total: dec!(0),
         liabilities_lp_total: dec!(0),
 
@@ -275,6 +616,17 @@ mod usd {
         exrd_vault: Vault::new(exrd_resource),
         xrd_vault: Vault::new(XRD),
         exrd_validator,
+        exrdxrd_cache: (dec!(1), Epoch::of(0u64)),
+        // EXRD/XRD only grows, a 1% drop in an epoch means something broke
+        exrd_max_drop: dec!("0.01"),
+        claim_vault: None,
+        unstakes: Vec::new(),
+        unstaking_xrd: dec!(0),
+        // a tenth liquid covers a run of withdrawals while the claims mature
+        unstake_buffer: dec!("0.1"),
+
+        collaterals: KeyValueStore::new(),
+        collateral_list: Vec::new(),
 
         // TODO candidate numbers
         ep: dec!("1.2"),
@@ -287,15 +639,70 @@ mod usd {
         // TODO candidate number
         maximum_minted: dec!("1000000"),
 
+        // free until governance says otherwise
+        borrow_rate: dec!(0),
+        last_accrual: Clock::current_time_rounded_to_minutes(),
+        interest_pending: dec!(0),
+        bad_debt: dec!(0),
+
+        // well over the fees of a close, so closing even a dust ECDP pays
+        close_refund: dec!(50),
+        deposit_vault: Vault::new(XRD),
+        ecdp_deposits: KeyValueStore::new(),
+        deposits_from: None,
+
+        // 0.5% to the redeemed ECDPs, for being picked to take the haircut
+        redemption_fee: dec!("0.005"),
+
+        // no penalty, what's over the debt is the SP's take, 1% to the liquidator
+        // covers its tx, and under $30 an ECDP isn't worth keeping around
+        liq_penalty: dec!(0),
+        liq_reward: dec!("0.01"),
+        dust: dec!(30),
+
+        ecdp_hooks: KeyValueStore::new(),
+        ecdp_notices: KeyValueStore::new(),
+        // twice the oracle staleness, a hook always gets a fresh price to act on
+        hook_grace: 10i64,
+
+        ecdp_protection: KeyValueStore::new(),
+        // same as the liquidator tip, it's the same kind of keeper work
+        protection_fee: dec!("0.01"),
+
+        sp_units: (sp_resource, dec!(0)),
+        sp_eusd: Vault::new(eusd_resource),
+        sp_exrd: Vault::new(exrd_resource),
+        sp_xrd: Vault::new(XRD),
+        sp_retired: None,
+
         flash_resource,
         fl_active: false,
         fm_active: false,
         flash_fee,
+        // same as the initial mint cap, no flash bigger than the whole supply
+        flash_max: (dec!(1000000), dec!(1000000)),
 
         oracle: oracle_init,
         oracle_timestamp: Clock::current_time_rounded_to_minutes(),
-        oracle1,
-        oracle2,
+        oracle_feeds: Self::initial_feeds(oracle1, oracle2),
+        // feeders post every minute or two, 5 minutes leaves room for a missed post
+        // and 10% a window is past any XRD move short of a crash, which should wait
+        oracle_staleness: 5i64,
+        oracle_max_deviation: dec!("0.1"),
+        oracle_anchor: (oracle_init, Clock::current_time_rounded_to_minutes()),
+        oracle_quarantine: dec!("0.05"),
+        oracle_pending: None,
+        oracle_bounds: (dec!("0.0001"), dec!(100)),
+        oracle_sources: vec![OracleSource::Feeders],
+        oracle_cross_deviation: dec!("0.05"),
+        eux_twap: (dec!(0), Instant::new(0i64)),
+
+        tripped: false,
+        // a confirmed 8% oracle step is inside the 10% the clamp lets through, so it can
+        // still fire, a 25% spot gap is past where the AA still helps
+        // under ep only new debt stops, under 110% the EUSD backing itself is at risk
+        // so the breaker is the last layer and also stops collateral leaving
+        breaker: (dec!("0.08"), dec!("0.25"), dec!("1.1")),
 
         stopped: true
       }
@@ -305,6 +712,10 @@ mod usd {
         roles!(
           azero => rule!(require(power_azero));
           dex => rule!(require(power_eux));
+          // until a guardian badge is set
+          guardian => rule!(require(power_azero));
+          // until a keeper badge is set
+          keeper => rule!(require(power_azero));
         )
       )
       .metadata(
@@ -335,12 +746,21 @@ mod usd {
       exrd_validator: ComponentAddress, 
       lower_bound: Decimal, upper_bound: Decimal, flash_fee: Decimal, bang: ComponentAddress,
       oracle_init: Decimal, oracle1: ResourceAddress, oracle2: ResourceAddress,
+      oracle_staleness: i64, oracle_max_deviation: Decimal,
       exrd: Bucket, xrd: Bucket,
       ecdp_resource: ResourceAddress, eusd_resource: ResourceAddress, 
+      sp_resource: Option<ResourceAddress>,
       assets_lp_total: Decimal, liabilities_lp_total: Decimal,
       liabilities_total: Decimal,
       ep: Decimal, mcr: Decimal, bp: Decimal, 
-      maximum_minted: Decimal) -> ComponentAddress {
+      maximum_minted: Decimal, borrow_rate: Decimal, redemption_fee: Decimal,
+      liq_penalty: Decimal, liq_reward: Decimal, dust: Decimal) -> ComponentAddress {
+
+      // the oracle step of the breaker has to fit under the clamp
+      assert!( oracle_max_deviation > dec!("0.08") && oracle_max_deviation < dec!(1),
+        "deviation out of bounds");
+      assert!( ep > dec!("1.1"),
+        "ep under the breaker floor");
       
       // recreate flash because we don't care about preserving it
       let flash_resource = 
@@ -364,6 +784,13 @@ mod usd {
         ))
         .create_with_no_initial_supply()
         .address();
+
+      // the SP units are the old one's, if it had an SP, so they're redeemable here
+      // after carry_over, its roles are on the power badge that came over
+      let sp_resource = sp_resource.unwrap_or_else(|| 
+        Self::sp_unit_resource(power_usd.resource_address(), bang));
+      let manager_resource = Self::manager_resource(power_usd.resource_address(), bang);
+      let exrd_resource = exrd.resource_address();
       
       Self {
         alpha_addr,
@@ -372,6 +799,21 @@ mod usd {
 
         ecdp_resource,
 
+        // the ECDPs from before have to be filed with ecdp_reindex
+        ecdp_buckets: KeyValueStore::new(),
+        ecdp_bucket_lens: KeyValueStore::new(),
+        ecdp_bucket_keys: Vec::new(),
+        ecdp_bucket_of: KeyValueStore::new(),
+
+        manager_resource,
+        ecdp_managers: KeyValueStore::new(),
+        ecdp_manager_index: KeyValueStore::new(),
+
+        iso_vaults: KeyValueStore::new(),
+        iso_list: Vec::new(),
+        iso_liabilities_total: dec!(0),
+        iso_liabilities_lp_total: dec!(0),
+
         assets_lp_total,
         liabilities_lp_total,
 
@@ -381,6 +823,17 @@ mod usd {
         exrd_vault: Vault::with_bucket(exrd),
         xrd_vault: Vault::with_bucket(xrd),
         exrd_validator,
+        exrdxrd_cache: (dec!(1), Epoch::of(0u64)),
+        // EXRD/XRD only grows, a 1% drop in an epoch means something broke
+        exrd_max_drop: dec!("0.01"),
+        claim_vault: None,
+        unstakes: Vec::new(),
+        unstaking_xrd: dec!(0),
+        // a tenth liquid covers a run of withdrawals while the claims mature
+        unstake_buffer: dec!("0.1"),
+
+        collaterals: KeyValueStore::new(),
+        collateral_list: Vec::new(),
 
         ep,
         mcr,
@@ -391,17 +844,62 @@ mod usd {
 
         maximum_minted,
 
+        borrow_rate,
+        last_accrual: Clock::current_time_rounded_to_minutes(),
+        interest_pending: dec!(0),
+        bad_debt: dec!(0),
+
+        close_refund: dec!(50),
+        deposit_vault: Vault::new(XRD),
+        ecdp_deposits: KeyValueStore::new(),
+        deposits_from: None,
+
+        redemption_fee,
+
+        liq_penalty,
+        liq_reward,
+        dust,
+
+        // the old one has none, starts out empty
+        ecdp_hooks: KeyValueStore::new(),
+        ecdp_notices: KeyValueStore::new(),
+        // twice the oracle staleness, a hook always gets a fresh price to act on
+        hook_grace: 10i64,
+
+        ecdp_protection: KeyValueStore::new(),
+        // same as the liquidator tip, it's the same kind of keeper work
+        protection_fee: dec!("0.01"),
+
+        sp_units: (sp_resource, dec!(0)),
+        sp_eusd: Vault::new(eusd_resource),
+        sp_exrd: Vault::new(exrd_resource),
+        sp_xrd: Vault::new(XRD),
+        sp_retired: None,
+
         flash_resource,
         fl_active: false,
         fm_active: false,
         flash_fee,
+        // same as the initial mint cap, no flash bigger than the whole supply
+        flash_max: (dec!(1000000), dec!(1000000)),
 
         // here it is the feed at moment of update
         oracle: oracle_init, 
         // arguably could be also passed as arg but might as well give it some extra time
         oracle_timestamp: Clock::current_time_rounded_to_minutes(),
-        oracle1,
-        oracle2,
+        oracle_feeds: Self::initial_feeds(oracle1, oracle2),
+        oracle_staleness,
+        oracle_max_deviation,
+        oracle_anchor: (oracle_init, Clock::current_time_rounded_to_minutes()),
+        oracle_quarantine: dec!("0.05"),
+        oracle_pending: None,
+        oracle_bounds: (dec!("0.0001"), dec!(100)),
+        oracle_sources: vec![OracleSource::Feeders],
+        oracle_cross_deviation: dec!("0.05"),
+        eux_twap: (dec!(0), Instant::new(0i64)),
+
+        tripped: false,
+        breaker: (dec!("0.08"), dec!("0.25"), dec!("1.1")),
 
         // no point in starting it stopped
         stopped: false
@@ -412,6 +910,10 @@ mod usd {
         roles!(
           azero => rule!(require(power_azero));
           dex => rule!(require(power_eux));
+          // until a guardian badge is set
+          guardian => rule!(require(power_azero));
+          // until a keeper badge is set
+          keeper => rule!(require(power_azero));
         )
       )
       .metadata(
@@ -434,6 +936,76 @@ mod usd {
       .address()
     }
 
+    fn sp_unit_resource(power_usd: ResourceAddress, bang: ComponentAddress) -> ResourceAddress {
+      ResourceBuilder::new_fungible(OwnerRole::None)
+        .metadata(metadata!(
+          roles {
+            metadata_setter => rule!(require(power_usd));
+            metadata_setter_updater => rule!(deny_all);
+            metadata_locker => rule!(deny_all);
+            metadata_locker_updater => rule!(deny_all);
+          },
+          init {
+            "name" => "Ethereal Stability Pool Unit".to_owned(), updatable;
+            "symbol" => "EUSP".to_owned(), updatable;
+            "dapp_definitions" =>
+              vec!(GlobalAddress::from(bang)), updatable;
+            "tags" => vec!["ethereal-dao".to_owned(), "stability-pool".to_owned()], updatable;
+            "info_url" => Url::of("https://ethereal.systems"), updatable;
+          }
+        ))
+        .mint_roles(mint_roles!(
+          minter => rule!(require(power_usd));
+          minter_updater => rule!(deny_all);
+        ))
+        .burn_roles(burn_roles!(
+          burner => rule!(require(power_usd));
+          burner_updater => rule!(deny_all);
+        ))
+        .create_with_no_initial_supply()
+        .address()
+    }
+
+    fn manager_resource(power_usd: ResourceAddress, bang: ComponentAddress) -> ResourceAddress {
+      ResourceBuilder::new_ruid_non_fungible::<EcdpManager>(OwnerRole::None)
+        .metadata(metadata!(
+          roles {
+            metadata_setter => rule!(require(power_usd));
+            metadata_setter_updater => rule!(deny_all);
+            metadata_locker => rule!(deny_all);
+            metadata_locker_updater => rule!(deny_all);
+          },
+          init {
+            "name" => "Ethereal ECDP Manager Badge".to_owned(), updatable;
+            "symbol" => "ECDPM", updatable;
+            "dapp_definitions" =>
+              vec!(GlobalAddress::from(bang)), updatable;
+            "tags" => vec!["ethereal-dao".to_owned(), "ecdp".to_owned()], updatable;
+            "info_url" => Url::of("https://ethereal.systems"), updatable;
+          }
+        ))
+        .mint_roles(mint_roles!(
+          minter => rule!(require(power_usd));
+          minter_updater => rule!(deny_all);
+        ))
+        .burn_roles(burn_roles!(
+          burner => rule!(require(power_usd));
+          burner_updater => rule!(deny_all);
+        ))
+        .create_with_no_initial_supply()
+        .address()
+    }
+
+    // both feeders start out as never having posted
+    fn initial_feeds(oracle1: ResourceAddress, oracle2: ResourceAddress) 
+      -> IndexMap<ResourceAddress, (Decimal, Instant)> {
+      let mut feeds = index_map_new();
+      feeds.insert(oracle1, (dec!(0), Instant::new(0i64)));
+      feeds.insert(oracle2, (dec!(0), Instant::new(0i64)));
+      feeds
+    }
+
+    // the rest goes through to_leftovers
     pub fn to_nothing(&mut self) -> (Bucket, Bucket, Bucket) {
       (
         self.power_usd.take_all(),
@@ -441,6 +1013,78 @@ mod usd {
         self.xrd_vault.take_all()
       )
     }
+
+    // empties out the stability pool, extra collateral, isolated and claim vaults
+    // for the next version's carry_over, after to_nothing in the same update
+    pub fn to_leftovers(&mut self) -> Leftovers {
+      assert!( self.power_usd.is_empty(),
+        "to_nothing first");
+
+      let collaterals = self.collateral_list.clone().iter().map(|ra| {
+        let mut c = self.collaterals.get_mut(ra).expect("collateral incoherence");
+        (c.vault.take_all(), c.source.clone(), c.factor, c.ceiling)
+      }).collect();
+
+      let isolated = self.iso_list.clone().iter().map(|id| {
+        let mut v = self.iso_vaults.get_mut(id).expect("isolated incoherence");
+        (id.clone(), v.0.take_all(), v.1.take_all())
+      }).collect();
+
+      let claims = self.claim_vault.as_mut().map(|v| v.take_all());
+      let sp_units = self.sp_units;
+      self.sp_units.1 = dec!(0);
+      let unstaking_xrd = self.unstaking_xrd;
+      self.unstaking_xrd = dec!(0);
+
+      Leftovers {
+        sp_units,
+        sp: (self.sp_eusd.take_all(), self.sp_exrd.take_all(), self.sp_xrd.take_all()),
+        collaterals,
+        isolated,
+        iso_totals: (self.iso_liabilities_total, self.iso_liabilities_lp_total),
+        claims,
+        unstakes: self.unstakes.drain(..).collect(),
+        unstaking_xrd,
+        bad_debt: self.bad_debt,
+        deposits: (self.deposit_vault.take_all(), Runtime::global_address())
+      }
+    }
+
+    // takes over what to_leftovers handed out, right after from_something
+    // which has to have been given the same SP unit resource
+    pub fn carry_over(&mut self, rest: Leftovers) {
+      assert!( self.sp_units.1 == dec!(0) && self.collateral_list.is_empty() 
+        && self.iso_list.is_empty() && self.claim_vault.is_none(),
+        "already carried over");
+      assert!( rest.sp_units.0 == self.sp_units.0,
+        "SP units of another resource");
+
+      self.sp_units = rest.sp_units;
+      self.sp_eusd.put(rest.sp.0);
+      self.sp_exrd.put(rest.sp.1);
+      self.sp_xrd.put(rest.sp.2);
+
+      for (holdings, source, factor, ceiling) in rest.collaterals {
+        let resource = holdings.resource_address();
+        self.collaterals.insert(resource, 
+          Collateral { vault: Vault::with_bucket(holdings), source, factor, ceiling });
+        self.collateral_list.push(resource);
+      }
+
+      for (id, exrd, xrd) in rest.isolated {
+        self.iso_vaults.insert(id.clone(), (Vault::with_bucket(exrd), Vault::with_bucket(xrd)));
+        self.iso_list.push(id);
+      }
+      self.iso_liabilities_total = rest.iso_totals.0;
+      self.iso_liabilities_lp_total = rest.iso_totals.1;
+
+      self.claim_vault = rest.claims.map(|b| Vault::with_bucket(b));
+      self.unstakes = rest.unstakes;
+      self.unstaking_xrd = rest.unstaking_xrd;
+      self.bad_debt = rest.bad_debt;
+      self.deposit_vault.put(rest.deposits.0);
+      self.deposits_from = Some(rest.deposits.1);
+    }
     
     pub fn look_within(&self) 
       -> (Decimal, Decimal, Decimal, Decimal) {
@@ -452,12 +1096,66 @@ mod usd {
       )
     }
 
+    pub fn eusd_resource(&self) -> ResourceAddress {
+      self.eusd_resource
+    }
+
     pub fn start_stop(&mut self, input: bool) {
       self.stopped = input;
     }
 
+    // Circuit breaker parts
+    // trips by itself on the oracle, the EUX spot (through aa_poke) or the TCR
+    // the guardian can trip it, only azero can untrip it
+
+    pub fn trip(&mut self) {
+      self.trip_breaker("guardian");
+    }
+
+    pub fn untrip(&mut self) {
+      self.tripped = false;
+    }
+
+    // permissionless, trips if the TCR fell under the floor
+    pub fn check_breaker(&mut self) -> bool {
+      // no price, no TCR, the staleness checks already stop what needs it
+      if self.guarded_get_oracle().is_none() {
+        return self.tripped
+      }
+      if !self.tripped && self.liabilities_total > dec!(0) && self.tcr() < self.breaker.2 {
+        self.trip_breaker("tcr");
+      }
+      self.tripped
+    }
+
+    // tripped, (oracle move, spot gap, TCR floor)
+    pub fn get_breaker(&self) -> (bool, (Decimal, Decimal, Decimal)) {
+      (self.tripped, self.breaker)
+    }
+
+    pub fn set_breaker(&mut self, oracle_move: Decimal, spot_gap: Decimal, tcr_floor: Decimal) {
+      assert!( oracle_move > dec!(0) && spot_gap > dec!(0) && tcr_floor > dec!(0),
+        "breaker params out of bounds");
+      // the clamp never lets a step past max deviation through
+      assert!( oracle_move < self.oracle_max_deviation,
+        "oracle move past the max deviation");
+      // ep stops new debt first, the breaker is the layer under it
+      assert!( tcr_floor < self.ep,
+        "TCR floor over ep");
+      self.breaker = (oracle_move, spot_gap, tcr_floor);
+    }
+
+    fn trip_breaker(&mut self, reason: &str) {
+      if !self.tripped {
+        self.tripped = true;
+        Runtime::emit_event(BreakerEvent { reason: reason.to_owned() });
+      }
+    }
+
     // easy access
-    pub fn get_params(&self) -> (Decimal, Decimal, Decimal, Decimal, Decimal, Decimal, Decimal) {
+    pub fn get_params(&self) 
+      -> (Decimal, Decimal, Decimal, Decimal, Decimal, Decimal, Decimal, Decimal, Decimal,
+        Decimal, Decimal, Decimal) {
       (
         self.ep,
         self.mcr,
@@ -465,13 +1163,37 @@ mod usd {
         self.lower_bound,
         self.upper_bound,
         self.maximum_minted,
-        self.flash_fee
+        self.flash_fee,
+        self.borrow_rate,
+        self.redemption_fee,
+        self.liq_penalty,
+        self.liq_reward,
+        self.dust
       )
     }
 
     // easy change
     pub fn set_params(&mut self, 
-      ep: Decimal, mcr: Decimal, bp: Decimal, lb: Decimal, ub: Decimal, mm: Decimal, ff: Decimal) {
+      ep: Decimal, mcr: Decimal, bp: Decimal, lb: Decimal, ub: Decimal, mm: Decimal, ff: Decimal,
+      br: Decimal, rf: Decimal, lp: Decimal, lr: Decimal, dust: Decimal) {
+      // ~31.5% a year
+      assert!( br >= dec!(0) && br <= dec!("0.00000001"),
+        "borrow rate out of bounds");
+      assert!( rf >= dec!(0) && rf < dec!(1),
+        "redemption fee out of bounds");
+      assert!( lp >= dec!(0) && lp <= dec!("0.2"),
+        "liquidation penalty out of bounds");
+      assert!( lr >= dec!(0) && lr <= dec!("0.1"),
+        "liquidator reward out of bounds");
+      assert!( dust >= dec!(0) && dust <= dec!(1000),
+        "dust threshold out of bounds");
+      assert!( ff >= dec!(1) && ff <= dec!("1.01"),
+        "flash fee out of bounds");
+      assert!( ep > self.breaker.2,
+        "ep under the breaker floor");
+
+      // whatever accrued so far, accrued at the old rate
+      self.accrue();
       
       self.ep = ep;
       self.mcr = mcr;
@@ -480,13 +1202,91 @@ mod usd {
       self.upper_bound = ub;
       self.maximum_minted = mm;
       self.flash_fee = ff;
+      self.borrow_rate = br;
+      self.redemption_fee = rf;
+      self.liq_penalty = lp;
+      self.liq_reward = lr;
+      self.dust = dust;
+    }
+
+    // simple interest since the last accrual, which happens on every interaction
+    // growing liabilities_total grows every ECDP's debt through liability_lp_usd
+    fn accrue(&mut self) {
+      self.refresh_exrdxrd();
+
+      let now = Clock::current_time_rounded_to_minutes();
+      let elapsed = now.seconds_since_unix_epoch - self.last_accrual.seconds_since_unix_epoch;
+
+      if elapsed <= 0i64 {
+        return
+      }
+
+      let interest = self.liabilities_total * self.borrow_rate * Decimal::from(elapsed);
+      let iso_interest = self.iso_liabilities_total * self.borrow_rate * Decimal::from(elapsed);
+
+      self.liabilities_total += interest;
+      self.iso_liabilities_total += iso_interest;
+      self.interest_pending += interest + iso_interest;
+      self.last_accrual = now;
+    }
+
+    // mints the accrued interest straight into the treasury
+    // what of it is needed to pay off the bad debt is never minted
+    pub fn sweep_interest(&mut self) {
+      self.accrue();
+
+      let written_off = 
+        if self.interest_pending < self.bad_debt { self.interest_pending } else { self.bad_debt };
+      self.bad_debt -= written_off;
+      self.interest_pending -= written_off;
+
+      if self.interest_pending == dec!(0) {
+        return
+      }
+
+      let pending = self.interest_pending;
+      self.interest_pending = dec!(0);
+
+      let eusd = self.power_usd.as_fungible().authorize_with_amount(dec!(1), ||
+        ResourceManager::from(self.eusd_resource).mint(pending)
+      );
+      self.delta().call_raw::<()>("deposit", scrypto_args!(eusd));
+    }
+
+    pub fn get_bad_debt(&self) -> Decimal {
+      self.bad_debt
+    }
+
+    // burns EUSD against the bad debt, returns what's over it
+    pub fn repay_bad_debt(&mut self, mut input: Bucket) -> Bucket {
+      assert!( input.resource_address() == self.eusd_resource,
+        "wrong resource");
+
+      let burn = input.take(if input.amount() < self.bad_debt { input.amount() } else { self.bad_debt });
+      self.bad_debt -= burn.amount();
+      self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+        ResourceManager::from(self.eusd_resource).burn(burn);
+      });
+
+      input
+    }
+
+    fn delta(&self) -> Global<AnyComponent> {
+      let alpha: Global<AnyComponent> = self.alpha_addr.into();
+      let dao: Global<AnyComponent> = 
+        alpha.call_raw::<ComponentAddress>("get_dao_addr", scrypto_args!()).into();
+
+      let (_, delta_ca, _) = 
+        dao.call_raw::<(ComponentAddress, ComponentAddress, ComponentAddress)>(
+          "get_branch_addrs", scrypto_args!()
+        );
+      delta_ca.into()
     }
 
     pub fn tcr(&mut self) -> Decimal {
+      self.accrue();
       if let Some(xrdusd) = self.guarded_get_oracle() {
-        let usd_xrd = self.xrd_vault.amount() * xrdusd; 
-        let usd_exrd = self.exrd_vault.amount() * xrdusd * self.exrdxrd();
-        return (usd_xrd + usd_exrd) / self.liabilities_total;
+        return self.assets_xrd() * xrdusd / self.liabilities_total;
       } 
       panic!("OUTDATED ORACLE");
     }
@@ -494,11 +1294,9 @@ mod usd {
     // returns tcr + assets in usd + liabilities in usd
     // purely a compt opt
     pub fn tcr_au_lu(&mut self) -> (Decimal, Decimal, Decimal) {
+      self.accrue();
       if let Some(xrdusd) = self.guarded_get_oracle() {
-        let usd_xrd = self.xrd_vault.amount() * xrdusd; 
-        let usd_exrd = self.exrd_vault.amount() * xrdusd * self.exrdxrd();
-
-        let au = usd_xrd + usd_exrd;
+        let au = self.assets_xrd() * xrdusd;
         return (au / self.liabilities_total, au, self.liabilities_total)
       } 
       panic!("OUTDATED ORACLE");
@@ -508,11 +1306,37 @@ mod usd {
     // system assumes no time value on unstake 
     // input as size in EXRD, inp 1 ~> returns >= 1
     // additionally it corrects the asset index by bumping it with stake rewards from exrd
+    // it only changes once an epoch, so it's cached for that epoch
     pub fn exrdxrd(&self) -> Decimal {
-      // fuck type safety
-      let valid: Global<AnyComponent> = self.exrd_validator.into();
+      if self.exrdxrd_cache.1 == Runtime::current_epoch() {
+        return self.exrdxrd_cache.0
+      }
+      self.validator().get_redemption_value(dec!(1))
+    }
+
+    // the rate only ever grows with rewards, a drop means something happened to the validator
+    // past the max drop, it trips the breaker (the new rate is still the one used)
+    fn refresh_exrdxrd(&mut self) {
+      let epoch = Runtime::current_epoch();
+      if self.exrdxrd_cache.1 == epoch {
+        return
+      }
+
+      let rate = self.validator().get_redemption_value(dec!(1));
+      if rate < self.exrdxrd_cache.0 * (dec!(1) - self.exrd_max_drop) {
+        self.trip_breaker("exrd");
+      }
+      self.exrdxrd_cache = (rate, epoch);
+    }
+
+    fn validator(&self) -> Global<Validator> {
+      self.exrd_validator.into()
+    }
 
-      valid.call_raw("get_redemption_value", scrypto_args!(dec!(1)))
+    pub fn set_exrd_max_drop(&mut self, max_drop: Decimal) {
+      assert!( max_drop >= dec!(0) && max_drop < dec!(1),
+        "max drop out of bounds");
+      self.exrd_max_drop = max_drop;
     }
 
     // conversion of asset_lp units 
@@ -523,9 +1347,7 @@ mod usd {
         if self.assets_lp_total == dec!(0) {
           return xrdusd
         }
-        let usd_xrd = self.xrd_vault.amount() * xrdusd; 
-        let usd_exrd = self.exrd_vault.amount() * xrdusd * self.exrdxrd();
-        return (usd_xrd + usd_exrd) / self.assets_lp_total;
+        return self.assets_xrd() * xrdusd / self.assets_lp_total;
       }
       panic!("OUTDATED ORACLE");
     }
@@ -537,8 +1359,44 @@ mod usd {
         return dec!(1)
       }
 
-      ( self.xrd_vault.amount() + self.exrdxrd()*self.exrd_vault.amount() ) 
-        / self.assets_lp_total
+      self.assets_xrd() / self.assets_lp_total
+    }
+
+    // XRD value of everything backing the ECDPs, extra collaterals at their factor
+    // XRD being unstaked at face value, see unstake_exrd
+    fn assets_xrd(&self) -> Decimal {
+      let mut xrd = self.xrd_vault.amount() + self.exrdxrd()*self.exrd_vault.amount() 
+        + self.unstaking_xrd;
+
+      for ra in self.collateral_list.iter() {
+        let (source, factor, amount) = {
+          let c = self.collaterals.get(ra).expect("collateral incoherence");
+          (c.source.clone(), c.factor, c.vault.amount())
+        };
+        xrd += Self::collateral_xrd(&source, factor, amount);
+      }
+
+      xrd
+    }
+
+    // factored XRD value of an amount of an extra collateral
+    fn collateral_xrd(source: &PriceSource, factor: Decimal, amount: Decimal) -> Decimal {
+      if amount == dec!(0) {
+        return dec!(0)
+      }
+
+      let price: Decimal = match source {
+        PriceSource::Lsu(validator) => {
+          let valid: Global<Validator> = (*validator).into();
+          valid.get_redemption_value(dec!(1))
+        },
+        PriceSource::Component(component, method) => {
+          let comp: Global<AnyComponent> = (*component).into();
+          comp.call_raw(method, scrypto_args!())
+        }
+      };
+
+      amount * price * factor
     }
 
     // conversion of liability_lp units 
@@ -550,64 +1408,694 @@ mod usd {
       return self.liabilities_total / self.liabilities_lp_total;
     }
 
-    // can technically create it underwater but that doesn't matter
-    // intended use is for treasury to create it at like 5x overcollat and not manage it at all
-    // ASSUMES EXRD INPUT, MINTS 777 EUSD, NO CR CHECKS
-    pub fn first_ecdp(&mut self, input: Bucket) -> (Bucket, Bucket) {
-      assert!( self.liabilities_lp_total == dec!(0) && self.assets_lp_total == dec!(0),
-        "not the first ecdp" );
+    // same, for the isolated ECDPs' liabilities lp
+    pub fn iso_liability_lp_usd(&self) -> Decimal {
+      if self.iso_liabilities_lp_total == dec!(0) {
+        return dec!(1)
+      }
+      return self.iso_liabilities_total / self.iso_liabilities_lp_total;
+    }
 
-      let ecdp = self.power_usd.as_fungible().authorize_with_amount(dec!(1), ||  
-        ResourceManager::from(self.ecdp_resource)
-          .mint_ruid_non_fungible(
-            Ecdp { assets_lp: dec!(0), liabilities_lp: dec!(0) }
-          )
-      );
+    // Isolated ECDP parts
 
-      let nft: NonFungible<Ecdp> = ecdp.as_non_fungible().non_fungible();
-      let id = nft.local_id();
+    fn is_isolated(&self, id: &NonFungibleLocalId) -> bool {
+      self.iso_vaults.get(id).is_some()
+    }
 
-      let assets_lp = self.exrdxrd()*input.amount();
-      let liabilities_lp = dec!("777");
+    // EXRD, XRD
+    fn iso_holdings(&self, id: &NonFungibleLocalId) -> (Decimal, Decimal) {
+      let v = self.iso_vaults.get(id).expect("isolated incoherence");
+      (v.0.amount(), v.1.amount())
+    }
 
-      self.assets_lp_total += assets_lp;
-      self.liabilities_lp_total += liabilities_lp;
-      self.liabilities_total = liabilities_lp;
+    // assets and debt of any ECDP, in USD
+    fn ecdp_values(&mut self, id: &NonFungibleLocalId, data: &Ecdp) -> (Decimal, Decimal) {
+      if self.is_isolated(id) {
+        let (exrd, xrd) = self.iso_holdings(id);
+        let xrdusd = self.guarded_get_oracle().expect("OUTDATED ORACLE");
+        (
+          (exrd * self.exrdxrd() + xrd) * xrdusd,
+          data.liabilities_lp * self.iso_liability_lp_usd()
+        )
+      } else {
+        (
+          data.assets_lp * self.asset_lp_usd(),
+          data.liabilities_lp * self.liability_lp_usd()
+        )
+      }
+    }
 
-      self.exrd_vault.put(input);
+    // EXRD and XRD held by an isolated ECDP, None if it's a pooled one
+    pub fn ecdp_isolated(&self, id: NonFungibleLocalId) -> Option<(Decimal, Decimal)> {
+      if self.is_isolated(&id) {
+        return Some(self.iso_holdings(&id))
+      }
+      None
+    }
 
-      Runtime::emit_event(
-        NewEcdpEvent { ecdp: id.clone() });
-      Runtime::emit_event(
-        EcdpAssetsEvent { ecdp: id.clone(), diff: assets_lp, new: assets_lp });
-      Runtime::emit_event(
-        EcdpLiabilitiesEvent { ecdp: id.clone(), diff: liabilities_lp, new: liabilities_lp });
+    // isolated ECDPs in the order they were opened
+    pub fn isolated_ecdps(&self, skip: u64, take: u64) -> Vec<NonFungibleLocalId> {
+      self.iso_list.iter()
+        .skip(skip as usize)
+        .take(take as usize)
+        .cloned()
+        .collect()
+    }
 
-      let eusd = self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
-        let rm = ResourceManager::from(self.ecdp_resource);
-        rm.update_non_fungible_data(&id, "assets_lp", 
-          assets_lp
-        );
-        rm.update_non_fungible_data(&id, "liabilities_lp", 
-          liabilities_lp
-        );
-        ResourceManager::from(self.eusd_resource).mint(dec!("777"))
-      });
+    // isolated liabilities lp total, liabilities total
+    pub fn iso_look(&self) -> (Decimal, Decimal) {
+      (
+        self.iso_liabilities_lp_total,
+        self.iso_liabilities_total
+      )
+    }
 
-      (ecdp, eusd)
+    // takes exact amounts out of an isolated ECDP
+    pub fn ecdp_uncollateralize_isolated(&mut self, exrd: Decimal, xrd: Decimal, p: Proof) 
+      -> (Bucket, Bucket) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!( exrd >= dec!(0) && xrd >= dec!(0) && exrd + xrd > dec!(0), 
+        "empty input");
+
+      self.accrue();
+
+      let recovery = self.recovery_state();
+      let (id, floor) = self.ecdp_access(p, true);
+
+      let out = self.uncollateralize_isolated_for(&id, exrd, xrd, self.mcr);
+      self.check_floor(&id, floor);
+      self.check_recovery(&id, recovery);
+      out
     }
 
-    // creates an empty ecdp
-    pub fn open_ecdp(&mut self, fee: Bucket) -> Bucket {
-      assert!( self.liabilities_lp_total != dec!(0) && self.assets_lp_total != dec!(0),
-        "need a first ecdp first" );
+    fn uncollateralize_isolated_for(&mut self, id: &NonFungibleLocalId, exrd: Decimal, xrd: Decimal, 
+      min_cr: Decimal) -> (Bucket, Bucket) {
+      let data: Ecdp = ResourceManager::from(self.ecdp_resource).get_non_fungible_data(id);
 
-      assert!( fee.amount() >= dec!("100"), 
-        "fee too small" );
+      let (held_exrd, held_xrd) = self.iso_holdings(id);
+      assert!( exrd <= held_exrd && xrd <= held_xrd,
+        "negative assets");
 
-      self.xrd_vault.put(fee);
-      
-      self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+      if data.liabilities_lp > dec!(0) {
+        let xrdusd = self.guarded_get_oracle().expect("OUTDATED ORACLE");
+        let cr = 
+          ((held_exrd - exrd) * self.exrdxrd() + held_xrd - xrd) * xrdusd
+          / ( data.liabilities_lp * self.iso_liability_lp_usd() );
+        assert!( cr >= min_cr, 
+          "cannot mint under mcr");
+      }
+
+      Runtime::emit_event(
+        EcdpIsolatedAssetsEvent { 
+          ecdp: id.clone(), 
+          exrd: held_exrd - exrd, 
+          xrd: held_xrd - xrd });
+
+      let mut v = self.iso_vaults.get_mut(id).expect("isolated incoherence");
+      (v.0.take(exrd), v.1.take(xrd))
+    }
+
+    // ECDP manager parts
+
+    // the ECDP a proof speaks for, either its badge or a manager badge with the rights for it
+    // draw -- minting or uncollateralizing, otherwise collateralizing or repaying
+    // returns the manager's cr floor to check against after a draw
+    fn ecdp_access(&self, p: Proof, draw: bool) -> (NonFungibleLocalId, Option<Decimal>) {
+      assert!( !draw || !self.tripped,
+        "circuit breaker tripped");
+
+      if p.resource_address() == self.ecdp_resource {
+        return (self.ecdp_owned(p), None)
+      }
+
+      let manager = p
+        .check(self.manager_resource)
+        .as_non_fungible()
+        .non_fungible_local_id();
+      let (id, rights) = self.ecdp_managers.get(&manager)
+        .map(|m| m.clone())
+        .expect("revoked manager");
+
+      if draw {
+        (id, Some(rights.cr_floor.expect("manager can't draw")))
+      } else {
+        assert!( rights.repay,
+          "manager can't repay");
+        (id, None)
+      }
+    }
+
+    // no debt left after a draw means assets left a debt-free ECDP (or one repaid in full),
+    // the floor bounds nothing there, so a manager can't do it at all
+    fn check_floor(&mut self, id: &NonFungibleLocalId, floor: Option<Decimal>) {
+      if let Some(floor) = floor {
+        let cr = self.ecdp_cr(id.clone())
+          .expect("manager can't draw from a debt-free ECDP");
+        assert!( cr >= floor,
+          "under the manager's cr floor");
+      }
+    }
+
+    fn ecdp_owned(&self, p: Proof) -> NonFungibleLocalId {
+      p
+        .check(self.ecdp_resource)
+        .as_non_fungible()
+        .non_fungible_local_id()
+    }
+
+    // mints a manager badge for the ECDP, owner only, 16 at most
+    pub fn ecdp_add_manager(&mut self, rights: EcdpManagerRights, p: Proof) -> Bucket {
+      let id = self.ecdp_owned(p);
+      assert!( self.managers_of(&id).len() < 16usize,
+        "too many managers");
+
+      let manager = self.power_usd.as_fungible().authorize_with_amount(dec!(1), ||  
+        ResourceManager::from(self.manager_resource)
+          .mint_ruid_non_fungible(
+            EcdpManager { ecdp: id.clone() }
+          )
+      );
+      let manager_id = manager.as_non_fungible().non_fungible_local_id();
+
+      Runtime::emit_event(
+        EcdpManagerEvent { 
+          ecdp: id.clone(), 
+          manager: manager_id.clone(), 
+          rights: Some(rights.clone()) });
+
+      let mut managers = self.managers_of(&id);
+      managers.push(manager_id.clone());
+      self.ecdp_manager_index.insert(id.clone(), managers);
+      self.ecdp_managers.insert(manager_id, (id, rights));
+      manager
+    }
+
+    pub fn ecdp_set_manager(&mut self, 
+      manager: NonFungibleLocalId, rights: EcdpManagerRights, p: Proof) {
+      let id = self.ecdp_owned(p);
+
+      {
+        let mut m = self.ecdp_managers.get_mut(&manager).expect("revoked manager");
+        assert!( m.0 == id,
+          "not this ecdp's manager");
+        m.1 = rights.clone();
+      }
+
+      Runtime::emit_event(
+        EcdpManagerEvent { ecdp: id, manager, rights: Some(rights) });
+    }
+
+    // the badge stays wherever it is, it just can't do anything anymore
+    pub fn ecdp_revoke_manager(&mut self, manager: NonFungibleLocalId, p: Proof) {
+      let id = self.ecdp_owned(p);
+
+      let managed = self.ecdp_managers.get(&manager).map(|m| m.0.clone());
+      assert!( managed == Some(id.clone()),
+        "not this ecdp's manager");
+      self.ecdp_managers.remove(&manager);
+
+      let mut managers = self.managers_of(&id);
+      managers.retain(|m| *m != manager);
+      self.ecdp_manager_index.insert(id.clone(), managers);
+
+      Runtime::emit_event(
+        EcdpManagerEvent { ecdp: id, manager, rights: None });
+    }
+
+    // revokes every manager of the ECDP, i.e. what a new owner does first
+    pub fn ecdp_revoke_managers(&mut self, p: Proof) {
+      let id = self.ecdp_owned(p);
+      self.revoke_managers(&id);
+    }
+
+    fn revoke_managers(&mut self, id: &NonFungibleLocalId) {
+      for manager in self.managers_of(id) {
+        self.ecdp_managers.remove(&manager);
+        Runtime::emit_event(
+          EcdpManagerEvent { ecdp: id.clone(), manager, rights: None });
+      }
+      self.ecdp_manager_index.remove(id);
+    }
+
+    fn managers_of(&self, id: &NonFungibleLocalId) -> Vec<NonFungibleLocalId> {
+      self.ecdp_manager_index.get(id).map(|m| m.clone()).unwrap_or_default()
+    }
+
+    // ECDP managed and the rights, None if revoked
+    pub fn get_manager(&self, manager: NonFungibleLocalId) 
+      -> Option<(NonFungibleLocalId, EcdpManagerRights)> {
+      self.ecdp_managers.get(&manager).map(|m| m.clone())
+    }
+
+    // managers of an ECDP and their rights
+    pub fn get_managers(&self, id: NonFungibleLocalId) 
+      -> Vec<(NonFungibleLocalId, EcdpManagerRights)> {
+      self.managers_of(&id).into_iter().map(|manager| {
+        let rights = self.ecdp_managers.get(&manager).expect("manager incoherence").1.clone();
+        (manager, rights)
+      }).collect()
+    }
+
+    // Compound ECDP parts
+
+    // assets and debt of an ECDP, in USD
+    pub fn ecdp_look(&mut self, id: NonFungibleLocalId) -> (Decimal, Decimal) {
+      self.accrue();
+      let data: Ecdp = ResourceManager::from(self.ecdp_resource).get_non_fungible_data(&id);
+      self.ecdp_values(&id, &data)
+    }
+
+    // mints size EUSD, checked against mcr
+    // the debt is rounded up to whole lps, so it can be over by the last decimal, never under
+    pub fn ecdp_mint_usd(&mut self, size: Decimal, p: Proof) -> Bucket {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!(size > dec!(0), 
+        "negative mint number");
+
+      self.accrue();
+
+      let recovery = self.recovery_state();
+      let (id, floor) = self.ecdp_access(p, true);
+
+      let out = self.mint_usd_for(&id, size);
+      self.check_floor(&id, floor);
+      self.check_recovery(&id, recovery);
+      out
+    }
+
+    // takes out size USD of collateral at the oracle, checked against mcr
+    // EXRD first, and if that runs out, XRD second, extra collaterals past those
+    pub fn ecdp_withdraw_usd(&mut self, size: Decimal, p: Proof) -> (Bucket, Option<Bucket>, Vec<Bucket>) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!(size > dec!(0), 
+        "empty input");
+
+      self.accrue();
+
+      let recovery = self.recovery_state();
+      let (id, floor) = self.ecdp_access(p, true);
+
+      let xrdusd = self.guarded_get_oracle().expect("OUTDATED ORACLE");
+      let out = self.withdraw_xrd_for(&id, size / xrdusd, self.mcr);
+      self.check_floor(&id, floor);
+      self.check_recovery(&id, recovery);
+      out
+    }
+
+    // open + collateralize + mint down to target_cr, one cr check at the end
+    pub fn open_ecdp_and_borrow(&mut self, 
+      fee: Bucket, collateral: Bucket, isolated: bool, target_cr: Decimal) -> (Bucket, Bucket) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!( target_cr >= self.mcr,
+        "target under mcr");
+      assert!( !self.tripped,
+        "circuit breaker tripped");
+
+      self.accrue();
+
+      let recovery = self.recovery_state();
+      let ecdp = self.open_ecdp(fee, isolated);
+      let id = ecdp.as_non_fungible().non_fungible_local_id();
+
+      self.collateralize_for(&id, collateral);
+
+      let data: Ecdp = ResourceManager::from(self.ecdp_resource).get_non_fungible_data(&id);
+      let (assets_usd, _) = self.ecdp_values(&id, &data);
+      let eusd = self.mint_usd_for(&id, assets_usd / target_cr);
+      self.check_recovery(&id, recovery);
+
+      (ecdp, eusd)
+    }
+
+    // burn + withdraw size USD of collateral, one cr check at the end
+    pub fn ecdp_burn_and_withdraw(&mut self, input: Bucket, size: Decimal, p: Proof) 
+      -> (Bucket, Option<Bucket>, Vec<Bucket>) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!(!input.is_empty() && size > dec!(0), 
+        "empty input");
+
+      self.accrue();
+
+      let recovery = self.recovery_state();
+      let (id, floor) = self.ecdp_access(p, true);
+
+      self.burn_for(&id, input);
+
+      let xrdusd = self.guarded_get_oracle().expect("OUTDATED ORACLE");
+      let out = self.withdraw_xrd_for(&id, size / xrdusd, self.mcr);
+      self.check_floor(&id, floor);
+      self.check_recovery(&id, recovery);
+      out
+    }
+
+    fn mint_usd_for(&mut self, id: &NonFungibleLocalId, size: Decimal) -> Bucket {
+      let lp_usd = 
+        if self.is_isolated(id) { self.iso_liability_lp_usd() } else { self.liability_lp_usd() };
+      // both divisions truncate, without it a flash repaid by minting could come up short
+      let mut lia_lp = size / lp_usd;
+      while lia_lp * lp_usd < size {
+        lia_lp += dec!("0.000000000000000001");
+      }
+      self.mint_for(id, lia_lp)
+    }
+
+    // XRD worth of collateral out of any kind of ECDP, leaving it at min_cr or over
+    fn withdraw_xrd_for(&mut self, id: &NonFungibleLocalId, xrd: Decimal, min_cr: Decimal) 
+      -> (Bucket, Option<Bucket>, Vec<Bucket>) {
+      if !self.is_isolated(id) {
+        let lp_xrd = self.asset_lp_xrd();
+        return self.uncollateralize_for(id, xrd / lp_xrd, false, min_cr)
+      }
+
+      let exrdxrd = self.exrdxrd();
+      let (held_exrd, _) = self.iso_holdings(id);
+      let (ret_exrd, ret_xrd) = 
+        if held_exrd * exrdxrd >= xrd {
+          self.uncollateralize_isolated_for(id, xrd / exrdxrd, dec!(0), min_cr)
+        } else {
+          self.uncollateralize_isolated_for(id, held_exrd, xrd - held_exrd * exrdxrd, min_cr)
+        };
+
+      if ret_xrd.is_empty() {
+        ret_xrd.drop_empty();
+        return (ret_exrd, None, Vec::new())
+      }
+      (ret_exrd, Some(ret_xrd), Vec::new())
+    }
+
+    pub fn dex_addr(&self) -> ComponentAddress {
+      let alpha: Global<AnyComponent> = self.alpha_addr.into();
+      let (_, e, _) = alpha.call_raw::<(ComponentAddress, ComponentAddress, ComponentAddress)>(
+        "get_app_addrs", scrypto_args!());
+      e
+    }
+
+    // the ECDP behind an ECDP or manager badge proof, no rights checked
+    pub fn proven_ecdp(&self, p: Proof) -> NonFungibleLocalId {
+      if p.resource_address() == self.ecdp_resource {
+        return self.ecdp_owned(p)
+      }
+
+      let manager = p
+        .check(self.manager_resource)
+        .as_non_fungible()
+        .non_fungible_local_id();
+      self.ecdp_managers.get(&manager).map(|m| m.0.clone()).expect("revoked manager")
+    }
+
+    // flash mint EUSD -> swap it for EXRD on Eux -> collateralize -> mint the flash back
+    // a function, as Eux calls back into Usd for the AA, which sits out under a flash
+    // sized at the oracle so the ECDP ends at target_cr or over, the CR checks are the ones of minting
+    // max_slippage -- %-expressed, the swap has to get at least the rest of the EUSD's worth in EXRD
+    //
+    // size X, assets A and debt D in USD, flash fee f, slippage s:
+    //   (A + X(1 - s)) / (D + Xf) = target_cr
+    pub fn leverage(usd_addr: ComponentAddress, p: Proof, 
+      target_cr: Decimal, max_slippage: Decimal, deadline: Option<Instant>) {
+      assert!( max_slippage >= dec!(0) && max_slippage < dec!(1),
+        "slippage out of bounds");
+
+      let usd: Global<AnyComponent> = usd_addr.into();
+      let eux: Global<AnyComponent> = 
+        usd.call_raw::<ComponentAddress>("dex_addr", scrypto_args!()).into();
+
+      let (assets, debt) = Self::proven_values(&usd, &p);
+      let kept = dec!(1) - max_slippage;
+      let ff = Self::flash_fee_of(&usd);
+      assert!( target_cr * ff > kept && assets > target_cr * debt,
+        "already at or under the target cr");
+      let size = (assets - target_cr * debt) / (target_cr * ff - kept);
+
+      let xrdusd = usd.call_raw::<Option<Decimal>>("guarded_get_oracle", scrypto_args!())
+        .expect("OUTDATED ORACLE");
+      let exrdxrd = usd.call_raw::<Decimal>("exrdxrd", scrypto_args!());
+      let min_out = size * kept / (xrdusd * exrdxrd);
+
+      let (eusd, flash) = usd.call_raw::<(Bucket, Bucket)>("flash_mint_start", scrypto_args!(size));
+      let data: Flash = flash.as_non_fungible().non_fungible().data();
+
+      let exrd = eux.call_raw::<Bucket>("guarded_swap", scrypto_args!(eusd, min_out, deadline));
+      usd.call_raw::<()>("ecdp_collateralize", scrypto_args!(exrd, p.clone()));
+
+      let repay = usd.call_raw::<Bucket>("ecdp_mint_usd", 
+        scrypto_args!(data.principal + data.fee, p.clone()));
+      usd.call_raw::<()>("flash_mint_end", scrypto_args!(repay, flash));
+      p.drop();
+    }
+
+    // flash mint EUSD -> repay with it -> withdraw collateral -> swap its EXRD for EUSD on Eux 
+    // -> repay the flash, what's left over is returned
+    // no XRD nor extra collateral market, those go back in, so less EXRD to sell and the swap fails
+    // sized at the oracle so the ECDP ends at target_cr or over
+    // max_slippage -- %-expressed, the swap has to get back at least the flash and its fee
+    //
+    // size X, assets A and debt D in USD, flash fee f, slippage s, selling Xf / (1 - s):
+    //   (A - Xf / (1 - s)) / (D - X) = target_cr
+    pub fn deleverage(usd_addr: ComponentAddress, p: Proof, 
+      target_cr: Decimal, max_slippage: Decimal, deadline: Option<Instant>) -> Bucket {
+      assert!( max_slippage >= dec!(0) && max_slippage < dec!(1),
+        "slippage out of bounds");
+
+      let usd: Global<AnyComponent> = usd_addr.into();
+      let eux: Global<AnyComponent> = 
+        usd.call_raw::<ComponentAddress>("dex_addr", scrypto_args!()).into();
+
+      let (assets, debt) = Self::proven_values(&usd, &p);
+      let sold_per = Self::flash_fee_of(&usd) / (dec!(1) - max_slippage);
+      assert!( target_cr > sold_per && target_cr * debt > assets,
+        "already at or over the target cr");
+      let size = ((target_cr * debt - assets) / (target_cr - sold_per)).min(debt);
+
+      let (eusd, flash) = usd.call_raw::<(Bucket, Bucket)>("flash_mint_start", scrypto_args!(size));
+      let data: Flash = flash.as_non_fungible().non_fungible().data();
+
+      usd.call_raw::<()>("ecdp_burn", scrypto_args!(eusd, p.clone()));
+      let (exrd, xrd, extras) = usd.call_raw::<(Bucket, Option<Bucket>, Vec<Bucket>)>(
+        "ecdp_withdraw_usd", scrypto_args!(size * sold_per, p.clone()));
+
+      for back in xrd.into_iter().chain(extras) {
+        if back.is_empty() {
+          back.drop_empty();
+        } else {
+          usd.call_raw::<()>("ecdp_collateralize", scrypto_args!(back, p.clone()));
+        }
+      }
+
+      let owed = data.principal + data.fee;
+      let mut out = eux.call_raw::<Bucket>("guarded_swap", scrypto_args!(exrd, owed, deadline));
+      let repay = out.take(owed);
+      usd.call_raw::<()>("flash_mint_end", scrypto_args!(repay, flash));
+      p.drop();
+      out
+    }
+
+    // assets and debt in USD of the ECDP behind the proof
+    fn proven_values(usd: &Global<AnyComponent>, p: &Proof) -> (Decimal, Decimal) {
+      let id = usd.call_raw::<NonFungibleLocalId>("proven_ecdp", scrypto_args!(p.clone()));
+      usd.call_raw::<(Decimal, Decimal)>("ecdp_look", scrypto_args!(id))
+    }
+
+    fn flash_fee_of(usd: &Global<AnyComponent>) -> Decimal {
+      let (_, _, _, _, _, _, ff, _, _, _, _, _) = usd.call_raw::<(Decimal, Decimal, Decimal, Decimal,
+        Decimal, Decimal, Decimal, Decimal, Decimal, Decimal, Decimal, Decimal)>("get_params", scrypto_args!());
+      ff
+    }
+
+    // ECDP registry
+
+    // None for debtless ECDPs, which aren't filed at all
+    // buckets are floor(ln(assets_lp / liabilities_lp) / ln(1.05))
+    fn cr_bucket(assets_lp: Decimal, liabilities_lp: Decimal) -> Option<Decimal> {
+      if liabilities_lp == dec!(0) {
+        return None
+      }
+      // all debt no assets, riskiest there is
+      if assets_lp == dec!(0) {
+        return Some(Decimal::MIN)
+      }
+
+      let ln = (assets_lp / liabilities_lp).ln().expect("registry incoherence");
+      Some( (ln / dec!("0.048790164169432003")).checked_floor().expect("registry incoherence") )
+    }
+
+    // (re)files the ECDP under the bucket of its new lp amounts
+    // has to be called after every change of the ECDP's data
+    // isolated ECDPs aren't filed, their lp amounts don't order with the pooled ones
+    fn file_ecdp(&mut self, id: &NonFungibleLocalId, assets_lp: Decimal, liabilities_lp: Decimal) {
+      if self.is_isolated(id) {
+        return
+      }
+
+      let new = Self::cr_bucket(assets_lp, liabilities_lp);
+      let old = self.ecdp_bucket_of.get(id).map(|x| *x);
+
+      if old.map(|(b, _)| b) == new {
+        return
+      }
+
+      // swap-remove, the last ECDP of the bucket takes the freed slot
+      if let Some((b, slot)) = old {
+        let len = self.bucket_len(b);
+        let last = self.ecdp_buckets.remove(&(b, len - 1)).expect("registry incoherence");
+        if slot != len - 1 {
+          self.ecdp_buckets.insert((b, slot), last.clone());
+          self.ecdp_bucket_of.insert(last, (b, slot));
+        }
+        if len == 1 {
+          self.ecdp_bucket_lens.remove(&b);
+          let ix = self.ecdp_bucket_keys.binary_search(&b).expect("registry incoherence");
+          self.ecdp_bucket_keys.remove(ix);
+        } else {
+          self.ecdp_bucket_lens.insert(b, len - 1);
+        }
+        self.ecdp_bucket_of.remove(id);
+      }
+
+      if let Some(b) = new {
+        let len = self.bucket_len(b);
+        if len == 0 {
+          if let Err(ix) = self.ecdp_bucket_keys.binary_search(&b) {
+            self.ecdp_bucket_keys.insert(ix, b);
+          }
+        }
+        self.ecdp_buckets.insert((b, len), id.clone());
+        self.ecdp_bucket_lens.insert(b, len + 1);
+        self.ecdp_bucket_of.insert(id.clone(), (b, len));
+      }
+    }
+
+    fn bucket_len(&self, b: Decimal) -> u64 {
+      self.ecdp_bucket_lens.get(&b).map(|x| *x).unwrap_or(0u64)
+    }
+
+    // files an ECDP from its current data, for ones that predate the registry
+    // harmless to call on anything else
+    pub fn ecdp_reindex(&mut self, id: NonFungibleLocalId) {
+      let data: Ecdp = ResourceManager::from(self.ecdp_resource).get_non_fungible_data(&id);
+      self.file_ecdp(&id, data.assets_lp, data.liabilities_lp);
+    }
+
+    // current CR of an ECDP, None if it has no debt
+    pub fn ecdp_cr(&mut self, id: NonFungibleLocalId) -> Option<Decimal> {
+      self.accrue();
+      let data: Ecdp = ResourceManager::from(self.ecdp_resource).get_non_fungible_data(&id);
+      if data.liabilities_lp == dec!(0) {
+        return None
+      }
+      let (assets_usd, debt_usd) = self.ecdp_values(&id, &data);
+      Some( assets_usd / debt_usd )
+    }
+
+    // pages through the ECDPs with debt, from the lowest CR up
+    // ordering is exact across buckets, within a bucket (~5% of CR) it is not
+    pub fn riskiest_ecdps(&self, skip: u64, take: u64) -> Vec<NonFungibleLocalId> {
+      let mut out: Vec<NonFungibleLocalId> = Vec::new();
+      let mut skip = skip;
+
+      for b in self.ecdp_bucket_keys.iter() {
+        if out.len() as u64 >= take {
+          break
+        }
+
+        let len = self.bucket_len(*b);
+        if skip >= len {
+          skip -= len;
+          continue
+        }
+
+        for slot in skip..len {
+          if out.len() as u64 >= take {
+            break
+          }
+          out.push(self.ecdp_buckets.get(&(*b, slot)).expect("registry incoherence").clone());
+        }
+        skip = 0;
+      }
+
+      out
+    }
+
+    // can technically create it underwater but that doesn't matter
+    // intended use is for treasury to create it at like 5x overcollat and not manage it at all
+    // ASSUMES EXRD INPUT, MINTS 777 EUSD, NO CR CHECKS
+    pub fn first_ecdp(&mut self, input: Bucket) -> (Bucket, Bucket) {
+      assert!( self.liabilities_lp_total == dec!(0) && self.assets_lp_total == dec!(0),
+        "not the first ecdp" );
+
+      let ecdp = self.power_usd.as_fungible().authorize_with_amount(dec!(1), ||  
+        ResourceManager::from(self.ecdp_resource)
+          .mint_ruid_non_fungible(
+            Ecdp { assets_lp: dec!(0), liabilities_lp: dec!(0) }
+          )
+      );
+
+      let nft: NonFungible<Ecdp> = ecdp.as_non_fungible().non_fungible();
+      let id = nft.local_id();
+
+      let assets_lp = self.exrdxrd()*input.amount();
+      let liabilities_lp = dec!("777");
+
+      self.assets_lp_total += assets_lp;
+      self.liabilities_lp_total += liabilities_lp;
+      self.liabilities_total = liabilities_lp;
+
+      self.exrd_vault.put(input);
+
+      Runtime::emit_event(
+        NewEcdpEvent { ecdp: id.clone() });
+      Runtime::emit_event(
+        EcdpAssetsEvent { ecdp: id.clone(), diff: assets_lp, new: assets_lp });
+      Runtime::emit_event(
+        EcdpLiabilitiesEvent { ecdp: id.clone(), diff: liabilities_lp, new: liabilities_lp });
+
+      let eusd = self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+        let rm = ResourceManager::from(self.ecdp_resource);
+        rm.update_non_fungible_data(&id, "assets_lp", 
+          assets_lp
+        );
+        rm.update_non_fungible_data(&id, "liabilities_lp", 
+          liabilities_lp
+        );
+        ResourceManager::from(self.eusd_resource).mint(dec!("777"))
+      });
+      self.file_ecdp(&id, assets_lp, liabilities_lp);
+
+      (ecdp, eusd)
+    }
+
+    // creates an empty ecdp
+    // isolated ones keep their own EXRD/XRD instead of a share of the pool, for good
+    pub fn open_ecdp(&mut self, fee: Bucket, isolated: bool) -> Bucket {
+      assert!( self.liabilities_lp_total != dec!(0) && self.assets_lp_total != dec!(0),
+        "need a first ecdp first" );
+
+      let out = self.mint_ecdp(fee);
+
+      if isolated {
+        let id = out.as_non_fungible().non_fungible_local_id();
+        self.iso_vaults.insert(id.clone(), 
+          (Vault::new(self.exrd_vault.resource_address()), Vault::new(XRD)));
+        self.iso_list.push(id);
+      }
+
+      out
+    }
+
+    // an empty ECDP badge for the fee, as open_ecdp and ecdp_split
+    fn mint_ecdp(&mut self, mut fee: Bucket) -> Bucket {
+      assert!( fee.amount() >= dec!("100") && fee.resource_address() == XRD, 
+        "fee too small" );
+
+      // the refundable part sits apart, the rest goes to the pooled ECDPs
+      self.deposit_vault.put(fee.take(self.close_refund));
+      self.xrd_vault.put(fee);
+      
+      let out = self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
         let out = ResourceManager::from(self.ecdp_resource)
           .mint_ruid_non_fungible(
             Ecdp { assets_lp: dec!(0), liabilities_lp: dec!(0) }
@@ -615,271 +2103,1449 @@ mod usd {
         Runtime::emit_event(
           NewEcdpEvent { ecdp: out.as_non_fungible().non_fungible_local_id() });
 
-        out
-      })
+        out
+      });
+      self.ecdp_deposits.insert(out.as_non_fungible().non_fungible_local_id(), self.close_refund);
+
+      out
+    }
+
+    // the part of the open_ecdp fee close_ecdp hands back
+    // none for ECDPs opened before it was kept apart, their fee went to the pooled ECDPs
+    pub fn deposit_of(&self, id: NonFungibleLocalId) -> Decimal {
+      if let Some(deposit) = self.ecdp_deposits.get(&id) {
+        return *deposit
+      }
+
+      match self.deposits_from {
+        Some(previous) => {
+          let previous: Global<AnyComponent> = previous.into();
+          previous.call_raw::<Decimal>("deposit_of", scrypto_args!(id))
+        },
+        None => dec!(0)
+      }
+    }
+
+    // repays all of the debt out of input, hands back all of the collateral and burns the badge
+    // returns EUSD change, EXRD, XRD (with part of the open_ecdp fee), extra collaterals
+    // extra collaterals are pooled and paid out as EXRD/XRD like the rest, while those last
+    pub fn close_ecdp(&mut self, ecdp: Bucket, mut input: Bucket) -> (Bucket, Bucket, Bucket, Vec<Bucket>) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!( ecdp.resource_address() == self.ecdp_resource && ecdp.amount() == dec!(1),
+        "not one ecdp");
+      assert!( input.resource_address() == self.eusd_resource,
+        "wrong resource");
+
+      self.accrue();
+
+      let rm = ResourceManager::from(self.ecdp_resource);
+      let id = ecdp.as_non_fungible().non_fungible_local_id();
+      let data: Ecdp = rm.get_non_fungible_data(&id);
+      let iso = self.is_isolated(&id);
+
+      // exact lp amounts off the totals, so nothing is left behind
+      let debt = data.liabilities_lp * 
+        if iso { self.iso_liability_lp_usd() } else { self.liability_lp_usd() };
+      assert!( input.amount() >= debt,
+        "not enough to repay");
+
+      if data.liabilities_lp > dec!(0) {
+        if iso {
+          self.iso_liabilities_total -= debt;
+          self.iso_liabilities_lp_total -= data.liabilities_lp;
+        } else {
+          self.liabilities_total -= debt;
+          self.liabilities_lp_total -= data.liabilities_lp;
+        }
+
+        let burn = input.take(debt);
+        self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+          ResourceManager::from(self.eusd_resource).burn(burn);
+        });
+
+        Runtime::emit_event(
+          EcdpLiabilitiesEvent { 
+            ecdp: id.clone(), 
+            diff: dec!("-1") * data.liabilities_lp, 
+            new: dec!(0) });
+      }
+
+      let (ret_exrd, mut ret_xrd, extras) = 
+        if iso {
+          // vaults can't be dropped, the emptied ones stay behind
+          let mut v = self.iso_vaults.get_mut(&id).expect("isolated incoherence");
+          (v.0.take_all(), v.1.take_all(), Vec::new())
+        } else {
+          let lp_xrd = self.asset_lp_xrd();
+          let (ret_exrd, ret_xrd, extras) = self.payout_in(data.assets_lp * lp_xrd, false);
+          self.assets_lp_total -= data.assets_lp;
+
+          Runtime::emit_event(
+            EcdpAssetsEvent { 
+              ecdp: id.clone(), 
+              diff: dec!("-1") * data.assets_lp, 
+              new: dec!(0) });
+
+          (ret_exrd, ret_xrd.unwrap_or_else(|| Bucket::new(XRD)), extras)
+        };
+
+      if iso {
+        self.iso_list.retain(|x| *x != id);
+        Runtime::emit_event(
+          EcdpIsolatedAssetsEvent { ecdp: id.clone(), exrd: dec!(0), xrd: dec!(0) });
+      }
+
+      // only what was put down for this ECDP, never the collateral
+      let refund = self.deposit_of(id.clone());
+      self.ecdp_deposits.remove(&id);
+      ret_xrd.put(self.deposit_vault.take(refund));
+
+      self.file_ecdp(&id, dec!(0), dec!(0));
+      self.ecdp_hooks.remove(&id);
+      self.ecdp_notices.remove(&id);
+      self.ecdp_protection.remove(&id);
+      self.revoke_managers(&id);
+
+      Runtime::emit_event(
+        EcdpClosedEvent { ecdp: id });
+
+      self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+        rm.burn(ecdp);
+      });
+
+      (input, ret_exrd, ret_xrd, extras)
+    }
+
+    // if can't mint, panics
+    pub fn ecdp_mint(&mut self, lia_lp: Decimal, p: Proof) -> Bucket {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!(lia_lp > dec!(0), 
+        "negative mint number");
+
+      self.accrue();
+
+      let recovery = self.recovery_state();
+      let (id, floor) = self.ecdp_access(p, true);
+
+      let out = self.mint_for(&id, lia_lp);
+      self.check_floor(&id, floor);
+      self.check_recovery(&id, recovery);
+      out
+    }
+
+    // puts lia_lp more debt on the ECDP, checked against mcr
+    fn mint_for(&mut self, id: &NonFungibleLocalId, lia_lp: Decimal) -> Bucket {
+      let rm = ResourceManager::from(self.ecdp_resource);
+      let data: Ecdp = rm.get_non_fungible_data(id);
+
+      let new_liabilities_lp = data.liabilities_lp + lia_lp;
+
+      let iso = self.is_isolated(id);
+      let lp_usd = if iso { self.iso_liability_lp_usd() } else { self.liability_lp_usd() };
+      let (assets_usd, _) = self.ecdp_values(id, &data);
+
+      let cr = assets_usd / ( new_liabilities_lp * lp_usd );
+      assert!( cr >= self.mcr, 
+        "cannot mint under mcr");
+      assert!( self.liabilities_total + self.iso_liabilities_total 
+        + new_liabilities_lp * lp_usd <= self.maximum_minted, 
+        "exceeded maximum minted");
+
+      Runtime::emit_event(
+        EcdpLiabilitiesEvent { ecdp: id.clone(), diff: lia_lp, new: new_liabilities_lp });
+
+      let minted = lia_lp * lp_usd;
+
+      if iso {
+        self.iso_liabilities_total += minted;
+        self.iso_liabilities_lp_total += lia_lp;
+      } else {
+        self.liabilities_total += minted;
+        self.liabilities_lp_total += lia_lp;
+      }
+      let out = self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+        rm.update_non_fungible_data(id, "liabilities_lp", 
+          new_liabilities_lp
+        );
+        ResourceManager::from(self.eusd_resource).mint(minted)
+      });
+      self.file_ecdp(id, data.assets_lp, new_liabilities_lp);
+
+      out
+    }
+
+    // if burns too much, panics
+    pub fn ecdp_burn(&mut self, input: Bucket, p: Proof) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!(!input.is_empty(), 
+        "empty input");
+
+      self.accrue();
+
+      let (id, _) = self.ecdp_access(p, false);
+
+      self.burn_for(&id, input);
+    }
+
+    // takes the EUSD off the ECDP's debt
+    fn burn_for(&mut self, id: &NonFungibleLocalId, input: Bucket) {
+      let rm = ResourceManager::from(self.ecdp_resource);
+      let data: Ecdp = rm.get_non_fungible_data(id);
+
+      let burn_amount = input.amount();
+      let iso = self.is_isolated(id);
+      let lp_usd = if iso { self.iso_liability_lp_usd() } else { self.liability_lp_usd() };
+
+      // prop_val = lp_usd * lp ~> lp = prop_val / lp_usd 
+      let new_liabilities_lp = data.liabilities_lp - burn_amount / lp_usd;
+      
+      // note: I can imagine a version of the system in which the 
+      // negative liabilities make sense
+      assert!( new_liabilities_lp >= dec!(0), 
+        "negative liabilities");
+
+      Runtime::emit_event(
+        EcdpLiabilitiesEvent { 
+          ecdp: id.clone(), 
+          diff: dec!("-1") * burn_amount / lp_usd, 
+          new: new_liabilities_lp });
+      
+      if iso {
+        self.iso_liabilities_total -= burn_amount;
+        self.iso_liabilities_lp_total -= burn_amount / lp_usd;
+      } else {
+        self.liabilities_total -= burn_amount;
+        self.liabilities_lp_total -= burn_amount / lp_usd;
+      }
+      self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+        rm.update_non_fungible_data(id, "liabilities_lp", 
+          new_liabilities_lp
+        );
+        ResourceManager::from(self.eusd_resource).burn(input);
+      });
+      self.file_ecdp(id, data.assets_lp, new_liabilities_lp);
+      self.clear_notice(id);
+    }
+
+    // absolutely no panics, ever
+    pub fn ecdp_collateralize(&mut self, input: Bucket, p: Proof) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!(!input.is_empty(), 
+        "empty input");
+
+      let (id, _) = self.ecdp_access(p, false);
+
+      self.collateralize_for(&id, input);
+    }
+
+    // puts the XRD or EXRD into the ECDP's assets
+    fn collateralize_for(&mut self, id: &NonFungibleLocalId, input: Bucket) {
+      if self.is_isolated(id) {
+        let (exrd, xrd) = {
+          let mut v = self.iso_vaults.get_mut(id).expect("isolated incoherence");
+          if input.resource_address() == v.0.resource_address() {
+            v.0.put(input);
+          } else {
+            assert!( input.resource_address() == XRD,
+              "isolated ECDPs only hold EXRD and XRD");
+            v.1.put(input);
+          }
+          (v.0.amount(), v.1.amount())
+        };
+
+        Runtime::emit_event(
+          EcdpIsolatedAssetsEvent { ecdp: id.clone(), exrd, xrd });
+        self.clear_notice(id);
+        return
+      }
+
+      let rm = ResourceManager::from(self.ecdp_resource);
+      let data: Ecdp = rm.get_non_fungible_data(id);
+
+      let size = input.amount();
+
+      let added_assets_lp = 
+        if input.resource_address() == self.exrd_vault.resource_address()
+        { let new = self.exrdxrd()*size / self.asset_lp_xrd();
+          self.exrd_vault.put(input);
+          new
+        } else if input.resource_address() == XRD {
+          let new = size / self.asset_lp_xrd();
+          self.xrd_vault.put(input);
+          new
+        } else {
+          self.collateralize_extra(input)
+        };
+      
+      Runtime::emit_event(
+        EcdpAssetsEvent { 
+          ecdp: id.clone(), 
+          diff: added_assets_lp, 
+          new: data.assets_lp + added_assets_lp });
+      
+      self.assets_lp_total += added_assets_lp;
+      self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+        rm.update_non_fungible_data(id, "assets_lp", 
+          data.assets_lp + added_assets_lp
+        );
+      });
+      self.file_ecdp(id, data.assets_lp + added_assets_lp, data.liabilities_lp);
+      self.clear_notice(id);
+    }
+
+    // puts an extra collateral into its vault, returns the assets_lp it is worth
+    fn collateralize_extra(&mut self, input: Bucket) -> Decimal {
+      let ra = input.resource_address();
+      let (source, factor, ceiling, held) = {
+        let c = self.collaterals.get(&ra).expect("not a collateral");
+        (c.source.clone(), c.factor, c.ceiling, c.vault.amount())
+      };
+
+      let lp_xrd = self.asset_lp_xrd();
+      let total_xrd = self.assets_xrd();
+      let added_xrd = Self::collateral_xrd(&source, factor, input.amount());
+      let held_xrd = Self::collateral_xrd(&source, factor, held);
+
+      assert!( self.liabilities_total * (held_xrd + added_xrd) / (total_xrd + added_xrd) <= ceiling,
+        "collateral ceiling reached");
+
+      self.collaterals.get_mut(&ra).expect("collateral incoherence").vault.put(input);
+
+      added_xrd / lp_xrd
+    }
+
+    // takes an exact amount of an extra collateral out of the ECDP
+    pub fn ecdp_uncollateralize_asset(&mut self, 
+      resource: ResourceAddress, amount: Decimal, p: Proof) -> Bucket {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!(amount > dec!(0), 
+        "empty input");
+
+      self.accrue();
+
+      let rm = ResourceManager::from(self.ecdp_resource);
+      let recovery = self.recovery_state();
+      let (id, floor) = self.ecdp_access(p, true);
+      let data: Ecdp = rm.get_non_fungible_data(&id);
+
+      assert!( !self.is_isolated(&id),
+        "isolated, use ecdp_uncollateralize_isolated");
+
+      let source = self.collaterals.get(&resource).expect("not a collateral").source.clone();
+
+      // at the full price, not the factored one the pool counts it at
+      // otherwise any ECDP could swap its EXRD for extra collateral at the factor's discount
+      let ass_lp = Self::collateral_xrd(&source, dec!(1), amount) / self.asset_lp_xrd();
+      let new_assets_lp = data.assets_lp - ass_lp;
+
+      assert!( new_assets_lp >= dec!(0),
+        "negative assets");
+
+      if data.liabilities_lp > dec!(0) {
+        let cr = 
+          new_assets_lp * self.asset_lp_usd()
+          / ( data.liabilities_lp * self.liability_lp_usd() );
+        assert!( cr >= self.mcr, 
+          "cannot mint under mcr");
+      }
+
+      Runtime::emit_event(
+        EcdpAssetsEvent { ecdp: id.clone(), diff: dec!("-1")*ass_lp, new: new_assets_lp });
+
+      self.assets_lp_total -= ass_lp;
+      self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+        rm.update_non_fungible_data(&id, "assets_lp", 
+          new_assets_lp
+        );
+      });
+      self.file_ecdp(&id, new_assets_lp, data.liabilities_lp);
+
+      let out = self.collaterals.get_mut(&resource).expect("collateral incoherence").vault.take(amount);
+      self.check_floor(&id, floor);
+      self.check_recovery(&id, recovery);
+      out
+    }
+
+    // resource, held, factor, ceiling
+    pub fn get_collaterals(&self) -> Vec<(ResourceAddress, Decimal, Decimal, Decimal)> {
+      self.collateral_list.iter().map(|ra| {
+        let c = self.collaterals.get(ra).expect("collateral incoherence");
+        (*ra, c.vault.amount(), c.factor, c.ceiling)
+      }).collect()
+    }
+
+    pub fn add_collateral(&mut self, 
+      resource: ResourceAddress, source: PriceSource, factor: Decimal, ceiling: Decimal) {
+      assert!( resource != XRD && resource != self.exrd_vault.resource_address()
+        && self.collaterals.get(&resource).is_none(),
+        "already a collateral");
+      assert!( factor > dec!(0) && factor <= dec!(1),
+        "collateral factor out of bounds");
+      assert!( ceiling >= dec!(0),
+        "negative ceiling");
+
+      self.collaterals.insert(resource, 
+        Collateral { vault: Vault::new(resource), source, factor, ceiling });
+      self.collateral_list.push(resource);
+    }
+
+    // can't be removed once in, a 0 ceiling stops any new deposits
+    pub fn set_collateral(&mut self, 
+      resource: ResourceAddress, source: PriceSource, factor: Decimal, ceiling: Decimal) {
+      assert!( factor > dec!(0) && factor <= dec!(1),
+        "collateral factor out of bounds");
+      assert!( ceiling >= dec!(0),
+        "negative ceiling");
+
+      let mut c = self.collaterals.get_mut(&resource).expect("not a collateral");
+      c.source = source;
+      c.factor = factor;
+      c.ceiling = ceiling;
+    }
+
+    // returns EXRD first, and if that runs out, XRD second, extra collaterals past those
+    pub fn ecdp_uncollateralize(&mut self, ass_lp: Decimal, p: Proof) 
+      -> (Bucket, Option<Bucket>, Vec<Bucket>) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!(ass_lp != dec!(0), 
+        "empty input");
+
+      self.accrue();
+
+      let recovery = self.recovery_state();
+      let (id, floor) = self.ecdp_access(p, true);
+
+      let out = self.uncollateralize_for(&id, ass_lp, false, self.mcr);
+      self.check_floor(&id, floor);
+      self.check_recovery(&id, recovery);
+      out
+    }
+
+    // same as ecdp_uncollateralize, xrd_first -- XRD first, and if that runs out, EXRD second
+    // still returns EXRD, XRD, extra collaterals
+    pub fn ecdp_uncollateralize_in(&mut self, ass_lp: Decimal, xrd_first: bool, p: Proof) 
+      -> (Bucket, Option<Bucket>, Vec<Bucket>) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty");
+      assert!(ass_lp != dec!(0), 
+        "empty input");
+
+      self.accrue();
+
+      let recovery = self.recovery_state();
+      let (id, floor) = self.ecdp_access(p, true);
+
+      let out = self.uncollateralize_for(&id, ass_lp, xrd_first, self.mcr);
+      self.check_floor(&id, floor);
+      self.check_recovery(&id, recovery);
+      out
+    }
+
+    // takes ass_lp out of a pooled ECDP, checked against mcr
+    fn uncollateralize_for(&mut self, id: &NonFungibleLocalId, ass_lp: Decimal, xrd_first: bool, 
+      min_cr: Decimal) -> (Bucket, Option<Bucket>, Vec<Bucket>) {
+      let rm = ResourceManager::from(self.ecdp_resource);
+      let data: Ecdp = rm.get_non_fungible_data(id);
+
+      assert!( !self.is_isolated(id),
+        "isolated, use ecdp_uncollateralize_isolated");
+
+      let new_assets_lp = data.assets_lp - ass_lp;
+
+      assert!( new_assets_lp >= dec!(0),
+        "negative assets");
+
+      if data.liabilities_lp > dec!(0) {
+        let cr = 
+          new_assets_lp * self.asset_lp_usd()
+          / ( data.liabilities_lp * self.liability_lp_usd() );
+        assert!( cr >= min_cr, 
+          "cannot mint under mcr");
+      }
+
+      let lp_xrd = self.asset_lp_xrd();
+
+      let (ret_exrd, ret_xrd, extras) = self.payout_in(ass_lp * lp_xrd, xrd_first);
+
+      Runtime::emit_event(
+        EcdpAssetsEvent { ecdp: id.clone(), diff: dec!("-1")*ass_lp, new: new_assets_lp });
+      
+      self.assets_lp_total -= ass_lp;
+      self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+        rm.update_non_fungible_data(id, "assets_lp", 
+          new_assets_lp
+        );
+      });
+      self.file_ecdp(id, new_assets_lp, data.liabilities_lp);
+
+      (ret_exrd, ret_xrd, extras)
+    }
+
+    // pays out XRD worth of collateral
+    // EXRD first, and if that runs out, XRD second
+    // only out of the two vaults, callers keep it within liquid_xrd
+    fn payout(&mut self, refund_xrd: Decimal) -> (Bucket, Option<Bucket>) {
+      self.payout_liquid(refund_xrd, false)
+    }
+
+    // xrd_first -- XRD first, and if that runs out, EXRD second
+    // past what the two vaults hold, extra collaterals in the order they were added,
+    // at their full price so the ECDPs left behind don't lose out on the factor
+    fn payout_in(&mut self, refund_xrd: Decimal, xrd_first: bool) -> (Bucket, Option<Bucket>, Vec<Bucket>) {
+      let liquid = self.liquid_xrd();
+      if refund_xrd <= liquid {
+        let (ret_exrd, ret_xrd) = self.payout_liquid(refund_xrd, xrd_first);
+        return (ret_exrd, ret_xrd, Vec::new())
+      }
+
+      let mut rest = refund_xrd - liquid;
+      let mut extras: Vec<Bucket> = Vec::new();
+      for ra in self.collateral_list.clone() {
+        if rest == dec!(0) {
+          break
+        }
+
+        let (source, held) = {
+          let c = self.collaterals.get(&ra).expect("collateral incoherence");
+          (c.source.clone(), c.vault.amount())
+        };
+        if held == dec!(0) {
+          continue
+        }
+
+        let price = Self::collateral_xrd(&source, dec!(1), dec!(1));
+        let take = 
+          if held * price <= rest {
+            rest -= held * price;
+            held
+          } else {
+            let take = rest / price;
+            rest = dec!(0);
+            take
+          };
+        extras.push(self.collaterals.get_mut(&ra).expect("collateral incoherence").vault.take(take));
+      }
+
+      assert!( rest == dec!(0),
+        "not enough to pay out, unstaking has to be claimed first");
+
+      (self.exrd_vault.take_all(), Some(self.xrd_vault.take_all()), extras)
+    }
+
+    // XRD value of what the EXRD and XRD vaults hold
+    fn liquid_xrd(&self) -> Decimal {
+      self.xrd_vault.amount() + self.exrdxrd()*self.exrd_vault.amount()
+    }
+
+    fn payout_liquid(&mut self, refund_xrd: Decimal, xrd_first: bool) -> (Bucket, Option<Bucket>) {
+      let exrdxrd =  self.exrdxrd();
+
+      if xrd_first {
+        if self.xrd_vault.amount() < refund_xrd {
+          let rest = refund_xrd - self.xrd_vault.amount();
+          let ret_xrd = Some(self.xrd_vault.take_all());
+          (self.exrd_vault.take(rest / exrdxrd), ret_xrd)
+        } else {
+          (Bucket::new(self.exrd_vault.resource_address()), Some(self.xrd_vault.take(refund_xrd)))
+        }
+      } else if exrdxrd*self.exrd_vault.amount() < refund_xrd {
+        // if the eexrd vault alone cannot pay out enough
+        let paidout = exrdxrd*self.exrd_vault.amount();
+        let ret_xrd = Some(self.xrd_vault.take(refund_xrd - paidout));
+        (self.exrd_vault.take_all(), ret_xrd)
+      } else {
+        (self.exrd_vault.take(refund_xrd / exrdxrd), None)
+      }
+    }
+
+    // Unstaking parts
+    // the reverse of the AA staking XRD, to manage the XRD/EXRD ratio in the vaults
+    // what's unstaking still counts in the assets, but can't be paid out until claimed
+    // so at least the buffer of the assets has to stay liquid in the vaults
+
+    // EXRD out of the vault into a claim NFT
+    pub fn unstake_exrd(&mut self, amount: Decimal) {
+      assert!( amount > dec!(0) && amount <= self.exrd_vault.amount(),
+        "wrong unstake size");
+
+      self.accrue();
+
+      let exrd = self.exrd_vault.take(amount);
+      let claim = self.validator().unstake(exrd);
+
+      for nf in claim.as_non_fungible().non_fungibles::<UnstakeData>() {
+        let data = nf.data();
+        self.unstaking_xrd += data.claim_amount;
+        self.unstakes.push((nf.local_id().clone(), data.claim_epoch, data.claim_amount));
+      }
+
+      if let Some(v) = self.claim_vault.as_mut() {
+        v.put(claim);
+      } else {
+        self.claim_vault = Some(Vault::with_bucket(claim));
+      }
+
+      assert!( self.liquid_xrd() >= self.unstake_buffer * self.assets_xrd(),
+        "unstake past the liquid buffer");
     }
 
-    // if can't mint, panics
-    pub fn ecdp_mint(&mut self, lia_lp: Decimal, p: Proof) -> Bucket {
+    // permissionless, claims whatever matured into the XRD vault
+    // returns the XRD claimed
+    pub fn claim_unstaked(&mut self) -> Decimal {
+      let epoch = Runtime::current_epoch();
+      let (matured, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.unstakes)
+        .into_iter()
+        .partition(|(_, claimable, _)| *claimable <= epoch);
+      self.unstakes = pending;
+
+      if matured.is_empty() {
+        return dec!(0)
+      }
+
+      let ids: IndexSet<NonFungibleLocalId> = matured.iter().map(|(id, _, _)| id.clone()).collect();
+      for (_, _, amount) in matured.iter() {
+        self.unstaking_xrd -= *amount;
+      }
+
+      let claims = self.claim_vault.as_mut().expect("claim incoherence")
+        .as_non_fungible()
+        .take_non_fungibles(&ids);
+      let xrd = self.validator().claim_xrd(claims.into());
+
+      let claimed = xrd.amount();
+      self.xrd_vault.put(xrd);
+      claimed
+    }
+
+    // XRD being unstaked, (claim NFT, claimable from, XRD)
+    pub fn unstaking_look(&self) -> (Decimal, Vec<(NonFungibleLocalId, Epoch, Decimal)>) {
+      (self.unstaking_xrd, self.unstakes.clone())
+    }
+
+    pub fn get_unstake_buffer(&self) -> Decimal {
+      self.unstake_buffer
+    }
+
+    pub fn set_unstake_buffer(&mut self, buffer: Decimal) {
+      assert!( buffer >= dec!(0) && buffer <= dec!(1),
+        "unstake buffer out of bounds");
+      self.unstake_buffer = buffer;
+    }
+
+    // burns EUSD for $1 worth of collateral at the oracle, minus the redemption fee
+    // the debt and collateral are taken off the riskiest ECDPs in the registry,
+    // skipping the ones under mcr (liquidate those instead)
+    // the fee stays with the redeemed ECDPs, whatever couldn't be redeemed is returned
+    pub fn redeem(&mut self, mut input: Bucket) 
+      -> (Bucket, Option<Bucket>, Bucket, Vec<Bucket>) {
       assert!( !self.stopped && !self.power_usd.is_empty(),
         "USD stopped or empty"); 
-      assert!(lia_lp > dec!(0), 
-        "negative mint number");
+      assert!( !self.fl_active && !self.fm_active,
+        "can't redeem during flash transactions");
+      assert!( !self.tripped,
+        "circuit breaker tripped");
+      assert!( input.resource_address() == self.eusd_resource,
+        "wrong resource");
+
+      self.accrue();
 
       let rm = ResourceManager::from(self.ecdp_resource);
-      let nft: NonFungible<Ecdp> = p
-        .check(self.ecdp_resource)
-        .as_non_fungible()
-        .non_fungible();
-      let id = nft.local_id();
-      let data = nft.data();
+      let ass_usd = self.asset_lp_usd();
+      let lia_usd = self.liability_lp_usd();
+      let lp_xrd = self.asset_lp_xrd();
 
-      let new_liabilities_lp = data.liabilities_lp + lia_lp;
+      let mut remaining = input.amount();
+      let mut redeemed_assets_lp = dec!(0);
+      let mut redeemed_liabilities_lp = dec!(0);
+
+      // bounded walk, one tx can only touch 20 ECDPs and look at 200
+      // the ones under mcr are paged past rather than blocking the redemption
+      // picked before any is touched, refiling shuffles the registry under the pages
+      let mut targets: Vec<(NonFungibleLocalId, Ecdp)> = Vec::new();
+      let mut covered = dec!(0);
+      let mut scanned = 0u64;
+      while covered < remaining && targets.len() < 20usize && scanned < 200u64 {
+        let page = self.riskiest_ecdps(scanned, 20u64);
+        if page.is_empty() {
+          break
+        }
+        scanned += page.len() as u64;
+
+        for id in page {
+          if covered >= remaining || targets.len() >= 20usize {
+            break
+          }
 
-      let lp_usd = self.liability_lp_usd();
+          let data: Ecdp = rm.get_non_fungible_data(&id);
+          if data.liabilities_lp == dec!(0) {
+            continue
+          }
 
-      let cr = 
-        self.asset_lp_usd()*data.assets_lp 
-        / ( new_liabilities_lp * lp_usd );
-      assert!( cr >= self.mcr, 
-        "cannot mint under mcr");
-      assert!( self.liabilities_total + new_liabilities_lp * lp_usd <= self.maximum_minted, 
-        "exceeded maximum minted");
+          let debt = data.liabilities_lp * lia_usd;
+          if data.assets_lp * ass_usd / debt < self.mcr {
+            continue
+          }
+
+          covered += debt;
+          targets.push((id, data));
+        }
+      }
+
+      for (id, data) in targets {
+        let debt = data.liabilities_lp * lia_usd;
+        let size = if debt < remaining { debt } else { remaining };
+        let lia_lp = size / lia_usd;
+        let ass_lp = size * (dec!(1) - self.redemption_fee) / ass_usd;
+
+        Runtime::emit_event(
+          EcdpAssetsEvent { 
+            ecdp: id.clone(), 
+            diff: dec!("-1") * ass_lp, 
+            new: data.assets_lp - ass_lp });
+        Runtime::emit_event(
+          EcdpLiabilitiesEvent { 
+            ecdp: id.clone(), 
+            diff: dec!("-1") * lia_lp, 
+            new: data.liabilities_lp - lia_lp });
+
+        self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+          rm.update_non_fungible_data(&id, "assets_lp", 
+            data.assets_lp - ass_lp
+          );
+          rm.update_non_fungible_data(&id, "liabilities_lp", 
+            data.liabilities_lp - lia_lp
+          );
+        });
+        self.file_ecdp(&id, data.assets_lp - ass_lp, data.liabilities_lp - lia_lp);
+
+        remaining -= size;
+        redeemed_assets_lp += ass_lp;
+        redeemed_liabilities_lp += lia_lp;
+      }
+
+      let redeemed = input.amount() - remaining;
 
       Runtime::emit_event(
-        EcdpLiabilitiesEvent { ecdp: id.clone(), diff: lia_lp, new: new_liabilities_lp });
+        RedemptionEvent { 
+          size: redeemed, 
+          fee: redeemed * self.redemption_fee });
 
-      let minted = lia_lp * lp_usd;
+      let (ret_exrd, ret_xrd, extras) = self.payout_in(redeemed_assets_lp * lp_xrd, false);
+
+      self.liabilities_total -= redeemed;
+      self.liabilities_lp_total -= redeemed_liabilities_lp;
+      self.assets_lp_total -= redeemed_assets_lp;
 
-      self.liabilities_total += minted;
-      self.liabilities_lp_total += lia_lp;
+      let burn = input.take(redeemed);
       self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
-        rm.update_non_fungible_data(&id, "liabilities_lp", 
-          new_liabilities_lp
-        );
-        ResourceManager::from(self.eusd_resource).mint(minted)
-      })
+        ResourceManager::from(self.eusd_resource).burn(burn);
+      });
+
+      (ret_exrd, ret_xrd, input, extras)
     }
 
-    // if burns too much, panics
-    pub fn ecdp_burn(&mut self, input: Bucket, p: Proof) {
+    // takes an id of the ECDP to liquidate
+    // if liquidated, returns liq_reward of the total assets as a liquidator tip
+    // i.e. makes them push the button even if the ecdp is bad debt
+    // the rest is subtracted from top and bottom 1:1, plus the liq_penalty on the debt
+    // the stability pool absorbs as much of the debt as it can, taking the same share of the seized assets
+    // what it cannot absorb is redistributed over every pooled ECDP 
+    // whatever assets are left, remain active and the game continues
+    pub fn liquidate(&mut self, 
+      liquidated_id: NonFungibleLocalId, 
+      liquidator_id: NonFungibleLocalId) {
       assert!( !self.stopped && !self.power_usd.is_empty(),
         "USD stopped or empty"); 
-      assert!(!input.is_empty(), 
-        "empty input");
+      assert!( !self.fl_active && !self.fm_active,
+        "can't liquidate during flash transactions");
 
+      self.accrue();
+      
       let rm = ResourceManager::from(self.ecdp_resource);
-      let nft: NonFungible<Ecdp> = p
-        .check(self.ecdp_resource)
-        .as_non_fungible()
-        .non_fungible();
-      let id = nft.local_id();
-      let data = nft.data();
+      let mut data_ted: Ecdp = rm.get_non_fungible_data(&liquidated_id);
 
-      let burn_amount = input.amount();
-      let lp_usd = self.liability_lp_usd();
+      let (mut assets_usd, mut debt_usd) = self.ecdp_values(&liquidated_id, &data_ted);
+      let liq_cr = self.liquidation_cr();
 
-      // prop_val = lp_usd * lp ~> lp = prop_val / lp_usd 
-      let new_liabilities_lp = data.liabilities_lp - burn_amount / lp_usd;
-      
-      // note: I can imagine a version of the system in which the 
-      // negative liabilities make sense
-      assert!( new_liabilities_lp >= dec!(0), 
-        "negative liabilities");
+      // CR must fall under MCR (bp in recovery mode) or value of assets under the dust threshold
+      if assets_usd / debt_usd >= liq_cr && assets_usd >= self.dust {
+        // no liquidation, back in range by the price, a notice from a past dip goes
+        self.ecdp_notices.remove(&liquidated_id);
+        return 
+      }
+
+      // last chance for the owner's hook, one call
+      // flashing flags up so the hook can't flash, liquidate or redeem in between, nor trigger the AA
+      // a hook that panics blocks this only until its notice is hook_grace old, then it's skipped
+      if let Some((component, method)) = self.live_hook(&liquidated_id) {
+        self.fl_active = true;
+        self.fm_active = true;
+        let hook: Global<AnyComponent> = component.into();
+        let (eusd, collateral) = hook.call_raw::<(Option<Bucket>, Option<Bucket>)>(
+          &method, scrypto_args!(liquidated_id.clone(), assets_usd / debt_usd));
+        self.fl_active = false;
+        self.fm_active = false;
+
+        if let Some(eusd) = eusd {
+          assert!( eusd.resource_address() == self.eusd_resource,
+            "wrong resource");
+          self.burn_for(&liquidated_id, eusd);
+        }
+        if let Some(collateral) = collateral {
+          self.collateralize_for(&liquidated_id, collateral);
+        }
+
+        data_ted = rm.get_non_fungible_data(&liquidated_id);
+        if data_ted.liabilities_lp == dec!(0) {
+          // paid off
+          self.ecdp_notices.remove(&liquidated_id);
+          return
+        }
+        (assets_usd, debt_usd) = self.ecdp_values(&liquidated_id, &data_ted);
+
+        if assets_usd / debt_usd >= liq_cr && assets_usd >= self.dust {
+          // saved
+          self.ecdp_notices.remove(&liquidated_id);
+          return 
+        }
+      }
+
+      Runtime::emit_event(
+        EcdpLiquidatedEvent { 
+          ecdp: liquidated_id.clone() });
+
+      if self.is_isolated(&liquidated_id) {
+        self.seize_isolated(&liquidated_id, &liquidator_id, data_ted, debt_usd);
+      } else {
+        self.seize_pooled(&liquidated_id, &liquidator_id, data_ted);
+      }
+      self.ecdp_notices.remove(&liquidated_id);
+    }
+
+    fn seize_pooled(&mut self, 
+      liquidated_id: &NonFungibleLocalId, liquidator_id: &NonFungibleLocalId, data_ted: Ecdp) {
+      let rm = ResourceManager::from(self.ecdp_resource);
+      let ass_usd = self.asset_lp_usd();
+      let lia_usd = self.liability_lp_usd();
+
+      // after tor cut
+      let assets_lp_total = data_ted.assets_lp * (dec!(1) - self.liq_reward);
+      let tor_cut = data_ted.assets_lp - assets_lp_total;
+      let assets_lp_usd_total = assets_lp_total * ass_usd;
+
+      let mut ted_remaining_usd = assets_lp_usd_total 
+        - data_ted.liabilities_lp * lia_usd * (dec!(1) + self.liq_penalty);
+
+      // if bad debt, wipe out
+      if ted_remaining_usd <= dec!(0) {
+        ted_remaining_usd = dec!(0);
+      }
+
+      let ted_remaining_assets = ted_remaining_usd * (dec!(1) / ass_usd);
+
+      let debt = data_ted.liabilities_lp * lia_usd;
+      let seized_lp = assets_lp_total - ted_remaining_assets;
+      let mut covered = if self.sp_eusd.amount() < debt { self.sp_eusd.amount() } else { debt };
+      // before the totals move
+      let lp_xrd = self.asset_lp_xrd();
+      // the SP is only paid in EXRD and XRD, past those the debt is redistributed
+      let liquid = self.liquid_xrd();
+      if seized_lp * lp_xrd * covered / debt > liquid {
+        covered = liquid * debt / (seized_lp * lp_xrd);
+      }
+      let sp_seized_lp = seized_lp * covered / debt;
+
+      self.assets_lp_total -= seized_lp;
+      self.liabilities_lp_total -= data_ted.liabilities_lp;
+
+      if covered > dec!(0) {
+        self.liabilities_total -= covered;
+
+        let sp_xrd = sp_seized_lp * lp_xrd;
+        let (exrd, xrd) = self.payout(if sp_xrd > liquid { liquid } else { sp_xrd });
+        self.sp_exrd.put(exrd);
+        if let Some(xrd) = xrd {
+          self.sp_xrd.put(xrd);
+        }
+
+        let burn = self.sp_eusd.take(covered);
+        self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+          ResourceManager::from(self.eusd_resource).burn(burn);
+        });
 
+        Runtime::emit_event(
+          StabilityPoolEvent { 
+            ecdp: liquidated_id.clone(), 
+            burned: covered, 
+            seized: sp_seized_lp * lp_xrd });
+      }
+
+      Runtime::emit_event(
+        EcdpAssetsEvent { 
+          ecdp: liquidated_id.clone(), 
+          diff: ted_remaining_assets - data_ted.assets_lp, 
+          new: ted_remaining_assets });
       Runtime::emit_event(
         EcdpLiabilitiesEvent { 
-          ecdp: id.clone(), 
-          diff: dec!("-1") * burn_amount / lp_usd, 
-          new: new_liabilities_lp });
+          ecdp: liquidated_id.clone(), 
+          diff: dec!("-1") * data_ted.liabilities_lp, 
+          new: dec!("0") });
       
-      self.liabilities_total -= burn_amount;
-      self.liabilities_lp_total -= burn_amount / lp_usd;
       self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
-        rm.update_non_fungible_data(&id, "liabilities_lp", 
-          new_liabilities_lp
+        rm.update_non_fungible_data(liquidated_id, "assets_lp", 
+          ted_remaining_assets
+        );
+        rm.update_non_fungible_data(liquidated_id, "liabilities_lp",
+          dec!(0)
         );
-        ResourceManager::from(self.eusd_resource).burn(input);
       });
+      self.file_ecdp(liquidated_id, ted_remaining_assets, dec!(0));
+
+      if self.is_isolated(liquidator_id) {
+        // can't hold lp, so the tip leaves the pool for real
+        // at most what the EXRD and XRD vaults hold, the rest stays with the pool
+        let tip_lp_xrd = self.asset_lp_xrd();
+        let liquid = self.liquid_xrd();
+        let tip_xrd = if tor_cut * tip_lp_xrd > liquid { liquid } else { tor_cut * tip_lp_xrd };
+        let (exrd, xrd) = self.payout(tip_xrd);
+        self.assets_lp_total -= tip_xrd / tip_lp_xrd;
+        self.collateralize_for(liquidator_id, exrd);
+        if let Some(xrd) = xrd {
+          self.collateralize_for(liquidator_id, xrd);
+        }
+      } else {
+        let data_tor: Ecdp = rm.get_non_fungible_data(liquidator_id);
+        Runtime::emit_event(
+          EcdpAssetsEvent { 
+            ecdp: liquidator_id.clone(), 
+            diff: tor_cut, 
+            new: data_tor.assets_lp + tor_cut });
+        self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+          rm.update_non_fungible_data(liquidator_id, "assets_lp", 
+            data_tor.assets_lp + tor_cut
+          );
+        });
+        self.file_ecdp(liquidator_id, data_tor.assets_lp + tor_cut, data_tor.liabilities_lp);
+      }
     }
 
-    // absolutely no panics, ever
-    pub fn ecdp_collateralize(&mut self, input: Bucket, p: Proof) {
-      assert!( !self.stopped && !self.power_usd.is_empty(),
-        "USD stopped or empty"); 
-      assert!(!input.is_empty(), 
-        "empty input");
-
+    // same cuts as the pooled one, but on the ECDP's own EXRD and XRD
+    // the part the stability pool can't absorb goes to the pooled ECDPs, assets and as much
+    // of the debt as those are worth, the rest of the debt is left as bad_debt
+    fn seize_isolated(&mut self, 
+      liquidated_id: &NonFungibleLocalId, liquidator_id: &NonFungibleLocalId, 
+      data_ted: Ecdp, debt: Decimal) {
       let rm = ResourceManager::from(self.ecdp_resource);
-      let nft: NonFungible<Ecdp> = p
-        .check(self.ecdp_resource)
-        .as_non_fungible()
-        .non_fungible();
-      let id = nft.local_id();
-      let data = nft.data();
+      let xrdusd = self.guarded_get_oracle().expect("OUTDATED ORACLE");
+      let exrdxrd = self.exrdxrd();
 
-      let size = input.amount();
+      let (exrd, xrd) = self.iso_holdings(liquidated_id);
+      let kept = dec!(1) - self.liq_reward;
+      let after_tip_usd = (exrd * exrdxrd + xrd) * kept * xrdusd;
+      let owed_usd = debt * (dec!(1) + self.liq_penalty);
+
+      // share of what's left after the tip that gets seized
+      let seized = if after_tip_usd <= owed_usd { dec!(1) } else { owed_usd / after_tip_usd };
+      let covered = if self.sp_eusd.amount() < debt { self.sp_eusd.amount() } else { debt };
+      let sp_share = covered / debt;
+
+      let (tip_exrd, tip_xrd, mut seized_exrd, mut seized_xrd, left_exrd, left_xrd) = {
+        let mut v = self.iso_vaults.get_mut(liquidated_id).expect("isolated incoherence");
+        let tip_exrd = v.0.take(exrd * self.liq_reward);
+        let tip_xrd = v.1.take(xrd * self.liq_reward);
+        let seized_exrd = v.0.take(exrd * kept * seized);
+        let seized_xrd = v.1.take(xrd * kept * seized);
+        (tip_exrd, tip_xrd, seized_exrd, seized_xrd, v.0.amount(), v.1.amount())
+      };
+
+      if covered > dec!(0) {
+        let sp_exrd = seized_exrd.take(seized_exrd.amount() * sp_share);
+        let sp_xrd = seized_xrd.take(seized_xrd.amount() * sp_share);
+        let sp_seized = sp_exrd.amount() * exrdxrd + sp_xrd.amount();
+        self.sp_exrd.put(sp_exrd);
+        self.sp_xrd.put(sp_xrd);
+
+        let burn = self.sp_eusd.take(covered);
+        self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+          ResourceManager::from(self.eusd_resource).burn(burn);
+        });
+
+        Runtime::emit_event(
+          StabilityPoolEvent { 
+            ecdp: liquidated_id.clone(), 
+            burned: covered, 
+            seized: sp_seized });
+      }
+
+      // redistribution, no lp minted for either
+      // with no pooled debt to spread it over, it all stays bad debt
+      let uncovered = debt - covered;
+      let seized_usd = (seized_exrd.amount() * exrdxrd + seized_xrd.amount()) * xrdusd;
+      let taken = 
+        if self.liabilities_lp_total == dec!(0) { dec!(0) }
+        else if seized_usd < uncovered { seized_usd } 
+        else { uncovered };
+      self.exrd_vault.put(seized_exrd);
+      self.xrd_vault.put(seized_xrd);
+      self.liabilities_total += taken;
+      self.bad_debt += uncovered - taken;
+
+      self.iso_liabilities_total -= debt;
+      self.iso_liabilities_lp_total -= data_ted.liabilities_lp;
 
-      let added_assets_lp = 
-        if input.resource_address() == self.exrd_vault.resource_address()
-        { let new = self.exrdxrd()*size / self.asset_lp_xrd();
-          self.exrd_vault.put(input);
-          new
-        } else {
-          let new = size / self.asset_lp_xrd();
-          self.xrd_vault.put(input);
-          new
-        };
-      
       Runtime::emit_event(
-        EcdpAssetsEvent { 
-          ecdp: id.clone(), 
-          diff: added_assets_lp, 
-          new: data.assets_lp + added_assets_lp });
-      
-      self.assets_lp_total += added_assets_lp;
+        EcdpIsolatedAssetsEvent { 
+          ecdp: liquidated_id.clone(), 
+          exrd: left_exrd, 
+          xrd: left_xrd });
+      Runtime::emit_event(
+        EcdpLiabilitiesEvent { 
+          ecdp: liquidated_id.clone(), 
+          diff: dec!("-1") * data_ted.liabilities_lp, 
+          new: dec!("0") });
+
       self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
-        rm.update_non_fungible_data(&id, "assets_lp", 
-          data.assets_lp + added_assets_lp
+        rm.update_non_fungible_data(liquidated_id, "liabilities_lp",
+          dec!(0)
         );
       });
+
+      if !tip_exrd.is_empty() {
+        self.collateralize_for(liquidator_id, tip_exrd);
+      } else {
+        tip_exrd.drop_empty();
+      }
+      if !tip_xrd.is_empty() {
+        self.collateralize_for(liquidator_id, tip_xrd);
+      } else {
+        tip_xrd.drop_empty();
+      }
+    }
+
+    // marks a liquidatable ECDP, starting the countdown after which its hook is skipped
+    pub fn liquidation_notice(&mut self, id: NonFungibleLocalId) {
+      assert!( self.ecdp_notices.get(&id).is_none(),
+        "already noticed");
+
+      self.accrue();
+      let data: Ecdp = ResourceManager::from(self.ecdp_resource).get_non_fungible_data(&id);
+      assert!( data.liabilities_lp > dec!(0),
+        "no debt");
+
+      let (assets_usd, debt_usd) = self.ecdp_values(&id, &data);
+      let liq_cr = self.liquidation_cr();
+      assert!( assets_usd / debt_usd < liq_cr || assets_usd < self.dust,
+        "not liquidatable");
+
+      self.ecdp_notices.insert(id, Clock::current_time_rounded_to_minutes());
+    }
+
+    // None removes the hook
+    pub fn ecdp_set_hook(&mut self, hook: Option<(ComponentAddress, String)>, p: Proof) {
+      let id = self.ecdp_owned(p);
+
+      match hook {
+        Some(hook) => { self.ecdp_hooks.insert(id, hook); },
+        None => { self.ecdp_hooks.remove(&id); }
+      }
+    }
+
+    pub fn set_hook_grace(&mut self, minutes: i64) {
+      assert!( minutes > 0i64 && minutes <= 1440i64,
+        "hook grace out of bounds");
+      self.hook_grace = minutes;
+    }
+
+    // drops the notice once the ECDP is out of liquidation range
+    // so a dust repay or top-up can't restart the hook's countdown
+    fn clear_notice(&mut self, id: &NonFungibleLocalId) {
+      if self.ecdp_notices.get(id).is_none() || self.guarded_get_oracle().is_none() {
+        return
+      }
+
+      let data: Ecdp = ResourceManager::from(self.ecdp_resource).get_non_fungible_data(id);
+      if data.liabilities_lp > dec!(0) {
+        let (assets_usd, debt_usd) = self.ecdp_values(id, &data);
+        if assets_usd / debt_usd < self.liquidation_cr() || assets_usd < self.dust {
+          return
+        }
+      }
+
+      self.ecdp_notices.remove(id);
+    }
+
+    // hook of the ECDP, unless there's none or its notice ran out
+    fn live_hook(&self, id: &NonFungibleLocalId) -> Option<(ComponentAddress, String)> {
+      let hook = self.ecdp_hooks.get(id).map(|h| h.clone())?;
+
+      if let Some(noticed) = self.ecdp_notices.get(id).map(|n| *n) {
+        if !Clock::current_time_is_at_or_before(
+          noticed.add_minutes(self.hook_grace).expect("time incoherence"), TimePrecision::Minute) {
+          return None
+        }
+      }
+
+      Some(hook)
+    }
+
+    // Recovery mode parts
+    // pooled TCR under bp -- draws can't lower the TCR and have to leave the ECDP at bp,
+    // liquidations start at bp instead of mcr
+    // under ep -- withdraw-only, no draw may add debt
+    // isolated debt doesn't count in the TCR, but isolated ECDPs are held to the same rules
+
+    // recovery mode, withdraw-only
+    // a view, so as of the last accrual
+    pub fn recovery_mode(&self) -> (bool, bool) {
+      if self.liabilities_total == dec!(0) {
+        return (false, false)
+      }
+      let xrdusd = self.guarded_get_oracle().expect("OUTDATED ORACLE");
+      let tcr = self.assets_xrd() * xrdusd / self.liabilities_total;
+      (tcr < self.bp, tcr < self.ep)
+    }
+
+    // TCR and total debt while in recovery mode, for check_recovery after the draw
+    fn recovery_state(&mut self) -> Option<(Decimal, Decimal)> {
+      if self.liabilities_total == dec!(0) {
+        return None
+      }
+      let tcr = self.tcr();
+      if tcr < self.bp {
+        Some((tcr, self.liabilities_total + self.iso_liabilities_total))
+      } else {
+        None
+      }
+    }
+
+    fn check_recovery(&mut self, id: &NonFungibleLocalId, before: Option<(Decimal, Decimal)>) {
+      if let Some((tcr, debt)) = before {
+        if tcr < self.ep {
+          assert!( self.liabilities_total + self.iso_liabilities_total <= debt,
+            "withdraw-only, TCR under ep");
+        }
+        if self.liabilities_total > dec!(0) {
+          assert!( self.tcr() >= tcr,
+            "recovery mode, can't lower the TCR");
+        }
+        if let Some(cr) = self.ecdp_cr(id.clone()) {
+          assert!( cr >= self.bp,
+            "recovery mode, ECDP under bp");
+        }
+      }
+    }
+
+    // CR under which ECDPs get liquidated
+    fn liquidation_cr(&mut self) -> Decimal {
+      if self.recovery_state().is_some() { self.bp } else { self.mcr }
+    }
+
+    // Merge and split parts
+    // pooled ECDPs only, isolated ones hold their own vaults
+
+    // moves everything of b into a, b is left empty (and can be closed or reused)
+    pub fn ecdp_merge(&mut self, proof_a: Proof, proof_b: Proof) {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty");
+
+      let a = self.ecdp_owned(proof_a);
+      let b = self.ecdp_owned(proof_b);
+      assert!( a != b,
+        "same ecdp");
+      assert!( !self.is_isolated(&a) && !self.is_isolated(&b),
+        "can't merge isolated ecdps");
+
+      self.accrue();
+
+      let recovery = self.recovery_state();
+      let rm = ResourceManager::from(self.ecdp_resource);
+      let data_a: Ecdp = rm.get_non_fungible_data(&a);
+      let data_b: Ecdp = rm.get_non_fungible_data(&b);
+
+      let assets_lp = data_a.assets_lp + data_b.assets_lp;
+      let liabilities_lp = data_a.liabilities_lp + data_b.liabilities_lp;
+      self.check_position(assets_lp, liabilities_lp);
+
+      self.move_position(&b, dec!(0), dec!(0), &data_b);
+      self.move_position(&a, assets_lp, liabilities_lp, &data_a);
+      self.ecdp_notices.remove(&b);
+      self.clear_notice(&a);
+      self.check_recovery(&a, recovery);
     }
 
-    // returns EXRD first, and if that runs out, XRD second
-    pub fn ecdp_uncollateralize(&mut self, ass_lp: Decimal, p: Proof) 
-      -> (Bucket, Option<Bucket>) {
+    // takes the given %-expressed parts of the assets and liabilities into a new ECDP
+    // which costs the same fee as open_ecdp
+    pub fn ecdp_split(&mut self, p: Proof, fee: Bucket, 
+      asset_fraction: Decimal, liability_fraction: Decimal) -> Bucket {
       assert!( !self.stopped && !self.power_usd.is_empty(),
-        "USD stopped or empty"); 
-      assert!(ass_lp != dec!(0), 
-        "empty input");
+        "USD stopped or empty");
+      assert!( asset_fraction >= dec!(0) && asset_fraction <= dec!(1) &&
+        liability_fraction >= dec!(0) && liability_fraction <= dec!(1),
+        "fractions out of bounds");
+      assert!( asset_fraction > dec!(0) || liability_fraction > dec!(0),
+        "nothing to split");
+
+      let id = self.ecdp_owned(p);
+      assert!( !self.is_isolated(&id),
+        "can't split isolated ecdps");
+
+      self.accrue();
 
+      let recovery = self.recovery_state();
       let rm = ResourceManager::from(self.ecdp_resource);
-      let nft: NonFungible<Ecdp> = p
-        .check(self.ecdp_resource)
-        .as_non_fungible()
-        .non_fungible();
-      let id = nft.local_id();
-      let data = nft.data();
+      let data: Ecdp = rm.get_non_fungible_data(&id);
 
-      let new_assets_lp = data.assets_lp - ass_lp;
+      let split_assets_lp = data.assets_lp * asset_fraction;
+      let split_liabilities_lp = data.liabilities_lp * liability_fraction;
+      let kept_assets_lp = data.assets_lp - split_assets_lp;
+      let kept_liabilities_lp = data.liabilities_lp - split_liabilities_lp;
 
-      assert!( new_assets_lp >= dec!(0),
-        "negative assets");
+      self.check_position(kept_assets_lp, kept_liabilities_lp);
+      self.check_position(split_assets_lp, split_liabilities_lp);
 
-      let cr = 
-        new_assets_lp * self.asset_lp_usd()
-        / ( data.liabilities_lp * self.liability_lp_usd() );
-      assert!( cr >= self.mcr, 
-        "cannot mint under mcr");
+      let out = self.mint_ecdp(fee);
+      let new_id = out.as_non_fungible().non_fungible_local_id();
+      let empty = Ecdp { assets_lp: dec!(0), liabilities_lp: dec!(0) };
 
-      let lp_xrd = self.asset_lp_xrd();
+      self.move_position(&id, kept_assets_lp, kept_liabilities_lp, &data);
+      self.move_position(&new_id, split_assets_lp, split_liabilities_lp, &empty);
+      self.clear_notice(&id);
+      self.check_recovery(&id, recovery);
+      self.check_recovery(&new_id, recovery);
 
-      let mut ret_xrd = None;
-      let ret_exrd = {
-        let refund_xrd = ass_lp * lp_xrd;
-        let exrdxrd =  self.exrdxrd();
+      out
+    }
 
-        if exrdxrd*self.exrd_vault.amount() < refund_xrd {
-          // if the eexrd vault alone cannot pay out enough
-          let paidout = exrdxrd*self.exrd_vault.amount();
-          ret_xrd = Some(self.xrd_vault.take(refund_xrd - paidout));
-          self.exrd_vault.take_all()
-        } else {
-          self.exrd_vault.take(refund_xrd / exrdxrd)
-        }
-      };
+    // MCR and dust of a pooled position, nothing to check without debt
+    fn check_position(&mut self, assets_lp: Decimal, liabilities_lp: Decimal) {
+      if liabilities_lp == dec!(0) {
+        return
+      }
+
+      let assets_usd = assets_lp * self.asset_lp_usd();
+      let debt_usd = liabilities_lp * self.liability_lp_usd();
+      assert!( assets_usd / debt_usd >= self.mcr,
+        "resulting position under mcr");
+      assert!( assets_usd >= self.dust,
+        "resulting position under dust");
+    }
 
+    // rewrites a pooled ECDP's lps, totals are unchanged by merging/splitting
+    fn move_position(&mut self, id: &NonFungibleLocalId, 
+      assets_lp: Decimal, liabilities_lp: Decimal, old: &Ecdp) {
       Runtime::emit_event(
-        EcdpAssetsEvent { ecdp: id.clone(), diff: dec!("-1")*ass_lp, new: new_assets_lp });
-      
-      self.assets_lp_total -= ass_lp;
+        EcdpAssetsEvent { ecdp: id.clone(), diff: assets_lp - old.assets_lp, new: assets_lp });
+      Runtime::emit_event(
+        EcdpLiabilitiesEvent { ecdp: id.clone(), diff: liabilities_lp - old.liabilities_lp, new: liabilities_lp });
+
       self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
-        rm.update_non_fungible_data(&id, "assets_lp", 
-          new_assets_lp
-        );
+        let rm = ResourceManager::from(self.ecdp_resource);
+        rm.update_non_fungible_data(id, "assets_lp", assets_lp);
+        rm.update_non_fungible_data(id, "liabilities_lp", liabilities_lp);
       });
+      self.file_ecdp(id, assets_lp, liabilities_lp);
+    }
+
+    // Protection parts
 
-      (ret_exrd, ret_xrd)
+    // None removes the rules
+    pub fn ecdp_set_protection(&mut self, rule: Option<ProtectionRule>, p: Proof) {
+      let id = self.ecdp_owned(p);
+
+      match rule {
+        Some(rule) => {
+          if let Some((trigger, part)) = rule.repay_under {
+            assert!( trigger > dec!(0) && part > dec!(0) && part <= dec!(1),
+              "repay rule out of bounds");
+          }
+          if let Some((trigger, target)) = rule.boost_over {
+            assert!( target >= self.mcr && target < trigger,
+              "boost rule out of bounds");
+          }
+          self.ecdp_protection.insert(id, rule);
+        },
+        None => { self.ecdp_protection.remove(&id); }
+      }
     }
 
-    // takes an id of the ECDP to liquidate
-    // if liquidated, returns 1% of the total assets as a liquidator tip
-    // i.e. makes them push the button even if the ecdp is bad debt
-    // the rest is subtracted from top and bottom 1:1
-    // whatever assets are left, remain active and the game continues
-    pub fn liquidate(&mut self, 
-      liquidated_id: NonFungibleLocalId, 
-      liquidator_id: NonFungibleLocalId) {
+    pub fn get_protection(&self, id: NonFungibleLocalId) -> Option<ProtectionRule> {
+      self.ecdp_protection.get(&id).map(|r| r.clone())
+    }
+
+    pub fn set_protection_fee(&mut self, fee: Decimal) {
+      assert!( fee >= dec!(0) && fee <= dec!("0.05"),
+        "protection fee out of bounds");
+      self.protection_fee = fee;
+    }
+
+    // permissionless, executes whichever rule of the ECDP is triggered at the guarded oracle
+    // Usd can't swap on Eux itself (Eux calls back in for the AA), so the executor brings the swap:
+    // repay -- EUSD, for the collateral at the oracle less the fee, the EUSD repays the debt
+    // boost -- EXRD/XRD, for EUSD minted at the oracle plus the fee, the collateral goes in
+    // returns what's owed to the executor and whatever was left of funds
+    pub fn execute_protection(&mut self, id: NonFungibleLocalId, mut funds: Bucket) -> Vec<Bucket> {
       assert!( !self.stopped && !self.power_usd.is_empty(),
-        "USD stopped or empty"); 
+        "USD stopped or empty");
       assert!( !self.fl_active && !self.fm_active,
-        "can't liquidate during flash transactions");
-      
-      let rm = ResourceManager::from(self.ecdp_resource);
-      let data_ted: Ecdp = rm.get_non_fungible_data(&liquidated_id);
+        "can't protect during flash transactions");
+      assert!( !self.tripped,
+        "circuit breaker tripped");
+
+      self.accrue();
+
+      let rule = self.ecdp_protection.get(&id).map(|r| r.clone()).expect("no protection");
+      let data: Ecdp = ResourceManager::from(self.ecdp_resource).get_non_fungible_data(&id);
+      assert!( data.liabilities_lp > dec!(0),
+        "no debt");
+
+      let xrdusd = self.guarded_get_oracle().expect("OUTDATED ORACLE");
+      let (assets_usd, debt_usd) = self.ecdp_values(&id, &data);
+      let cr = assets_usd / debt_usd;
+      let recovery = self.recovery_state();
+
+      if let Some((_, part)) = rule.repay_under.filter(|(trigger, _)| cr < *trigger) {
+        assert!( funds.resource_address() == self.eusd_resource,
+          "repaying takes EUSD");
+
+        // no point selling more than the whole debt
+        let mut size = assets_usd * part;
+        let cap = debt_usd / (dec!(1) - self.protection_fee);
+        if size > cap {
+          size = cap;
+        }
 
-      let ass_usd = self.asset_lp_usd();
-      let lia_usd = self.liability_lp_usd();
-      let liq_cr = (data_ted.assets_lp * ass_usd) 
-              / (data_ted.liabilities_lp * lia_usd);
+        // no mcr check on the withdrawal, the point is to get under-mcr ECDPs out of there
+        // instead the repay has to raise the ECDP's CR, and in recovery mode not lower the TCR
+        let repay = funds.take(size * (dec!(1) - self.protection_fee));
+        self.burn_for(&id, repay);
+        let (exrd, xrd, extras) = self.withdraw_xrd_for(&id, size / xrdusd, dec!(0));
 
-      // CR must fall under MCR or value of assets under 30 bucks
-      if liq_cr >= self.mcr && ( ass_usd >= dec!(30) ) {
-        // no liquidation
-        return 
+        if let Some(new_cr) = self.ecdp_cr(id.clone()) {
+          assert!( new_cr > cr,
+            "repay didn't raise the CR");
+        }
+        if let Some((tcr, _)) = recovery {
+          if self.liabilities_total > dec!(0) {
+            assert!( self.tcr() >= tcr,
+              "recovery mode, can't lower the TCR");
+          }
+        }
+
+        Runtime::emit_event(
+          ProtectionEvent { ecdp: id, repay: true, size });
+
+        let mut out = vec![funds, exrd];
+        if let Some(xrd) = xrd {
+          out.push(xrd);
+        }
+        out.extend(extras);
+        return out
       }
 
-      // TODO liquidation hook
+      if let Some((_, target)) = rule.boost_over.filter(|(trigger, _)| cr > *trigger) {
+        let size = 
+          if funds.resource_address() == XRD {
+            funds.amount() * xrdusd
+          } else {
+            assert!( funds.resource_address() == self.exrd_vault.resource_address(),
+              "boosting takes EXRD or XRD");
+            funds.amount() * self.exrdxrd() * xrdusd
+          };
 
-      // after tor cut --- todo, tor cut a parameter?
-      let assets_lp_total = data_ted.assets_lp * dec!("0.99");
-      let tor_cut = data_ted.assets_lp - assets_lp_total;
-      let assets_lp_usd_total = assets_lp_total * ass_usd;
+        self.collateralize_for(&id, funds);
+        let eusd = self.mint_usd_for(&id, size * (dec!(1) + self.protection_fee));
+        self.check_recovery(&id, recovery);
 
-      let mut ted_remaining_usd = assets_lp_usd_total - data_ted.liabilities_lp * lia_usd;
+        let new_cr = self.ecdp_cr(id.clone()).expect("incoherence");
+        assert!( new_cr >= target,
+          "boosted past the target");
 
-      // if bad debt, wipe out
-      if ted_remaining_usd <= dec!(0) {
-        ted_remaining_usd = dec!(0);
+        Runtime::emit_event(
+          ProtectionEvent { ecdp: id, repay: false, size });
+
+        return vec![eusd]
       }
 
-      let ted_remaining_assets = ted_remaining_usd * (dec!(1) / ass_usd);
+      panic!("nothing triggered");
+    }
 
-      self.assets_lp_total -= assets_lp_total - ted_remaining_assets;
-      self.liabilities_lp_total -= data_ted.liabilities_lp;
+    // Stability Pool parts
 
-      let data_tor: Ecdp = rm.get_non_fungible_data(&liquidator_id);
-      Runtime::emit_event(
-        EcdpLiquidatedEvent { 
-          ecdp: liquidated_id.clone() });
-      Runtime::emit_event(
-        EcdpAssetsEvent { 
-          ecdp: liquidated_id.clone(), 
-          diff: ted_remaining_assets - data_ted.assets_lp, 
-          new: ted_remaining_assets });
-      Runtime::emit_event(
-        EcdpLiabilitiesEvent { 
-          ecdp: liquidated_id.clone(), 
-          diff: dec!("-1") * data_ted.liabilities_lp, 
-          new: dec!("0") });
-      Runtime::emit_event(
-        EcdpAssetsEvent { 
-          ecdp: liquidator_id.clone(), 
-          diff: tor_cut, 
-          new: data_tor.assets_lp + tor_cut });
-      
-      self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
-        rm.update_non_fungible_data(&liquidated_id, "assets_lp", 
-          ted_remaining_assets
-        );
-        rm.update_non_fungible_data(&liquidated_id, "liabilities_lp",
-          dec!(0)
-        );
-        
-        rm.update_non_fungible_data(&liquidator_id, "assets_lp", 
-          data_tor.assets_lp + tor_cut
+    // mints units worth the deposit, with the pool valued at the oracle
+    pub fn sp_deposit(&mut self, input: Bucket) -> Bucket {
+      assert!( !self.stopped && !self.power_usd.is_empty(),
+        "USD stopped or empty"); 
+      assert!( input.resource_address() == self.eusd_resource,
+        "wrong resource");
+
+      let size = input.amount();
+      let value = self.sp_value_usd();
+      let minted = 
+        if self.sp_units.1 == dec!(0) || value == dec!(0) {
+          size
+        } else {
+          size * self.sp_units.1 / value
+        };
+
+      self.sp_eusd.put(input);
+      self.sp_units.1 += minted;
+      self.power_usd.as_fungible().authorize_with_amount(dec!(1), || 
+        ResourceManager::from(self.sp_units.0).mint(minted)
+      )
+    }
+
+    // pro rata of everything in the pool, EUSD / EXRD / XRD
+    // works stopped or emptied too, so the pool can always be left
+    pub fn sp_withdraw(&mut self, input: Bucket) -> (Bucket, Bucket, Bucket) {
+      assert!( input.resource_address() == self.sp_units.0,
+        "wrong resource");
+      // none left after to_leftovers, the units are redeemable at the new one
+      assert!( self.sp_units.1 > dec!(0),
+        "SP carried over");
+
+      let per = input.amount() / self.sp_units.1;
+      self.sp_units.1 -= input.amount();
+      if self.power_usd.is_empty() {
+        if let Some(v) = self.sp_retired.as_mut() {
+          v.put(input);
+        } else {
+          self.sp_retired = Some(Vault::with_bucket(input));
+        }
+      } else {
+        self.power_usd.as_fungible().authorize_with_amount(dec!(1), || 
+          ResourceManager::from(self.sp_units.0).burn(input)
         );
-      });
+      }
+
+      (
+        self.sp_eusd.take(self.sp_eusd.amount() * per),
+        self.sp_exrd.take(self.sp_exrd.amount() * per),
+        self.sp_xrd.take(self.sp_xrd.amount() * per)
+      )
+    }
+
+    // EUSD, EXRD, XRD, units outstanding
+    pub fn sp_look(&self) -> (Decimal, Decimal, Decimal, Decimal) {
+      (
+        self.sp_eusd.amount(),
+        self.sp_exrd.amount(),
+        self.sp_xrd.amount(),
+        self.sp_units.1
+      )
+    }
+
+    fn sp_value_usd(&self) -> Decimal {
+      if let Some(xrdusd) = self.guarded_get_oracle() {
+        let xrd = self.sp_xrd.amount() + self.sp_exrd.amount() * self.exrdxrd();
+        return self.sp_eusd.amount() + xrd * xrdusd
+      }
+      panic!("OUTDATED ORACLE");
     }
 
     // Flash Mint / Loan parts
@@ -893,9 +3559,13 @@ mod usd {
     pub fn flash_loan_start(&mut self, size: Decimal, res: bool) -> (Bucket, Bucket) {
       assert!( !self.stopped && !self.power_usd.is_empty(),
         "USD stopped or empty"); 
+      assert!( !self.tripped,
+        "circuit breaker tripped");
       assert!(size <= if res { self.exrd_vault.amount() } else { self.xrd_vault.amount() },
         "our size is not size enough"
       );
+      assert!( size * if res { self.exrdxrd() } else { dec!(1) } <= self.flash_max.1,
+        "over the flash loan limit");
       assert!(!self.fl_active, 
         "twice flash loaned");
 
@@ -917,7 +3587,8 @@ mod usd {
         ResourceManager::from(self.flash_resource)
           .mint_ruid_non_fungible(
             Flash {
-              size: size*self.flash_fee,
+              principal: size,
+              fee: size * (self.flash_fee - dec!(1)),
               isloan: Some(res)
             }
           ));
@@ -928,57 +3599,49 @@ mod usd {
     // basically allows a very cheap swap between either
     // need to price it like a dex swap
     // i.e. at least 0.1% (ref: aave 0.09%) 
-    pub fn flash_loan_end(&mut self, input: Bucket, flash: Bucket) {
+    // the principal goes back into the vaults, the fee (and anything over it) to Delta
+    pub fn flash_loan_end(&mut self, mut input: Bucket, flash: Bucket) {
       assert!(flash.resource_address() == self.flash_resource,
         "not flash");
       assert!(self.fl_active, 
         "twice flash loaned");
 
       let data: Flash = flash.as_non_fungible().non_fungible().data();
+      let repaid_exrd = input.resource_address() == self.exrd_vault.resource_address();
+      assert!( repaid_exrd || input.resource_address() == XRD,
+        "wrong resource");
 
+      // principal and fee in what was repaid
       let exrdxrd = self.exrdxrd();
-      
-      match data.isloan {
-        Some(true) => {
-          if input.resource_address() == self.exrd_vault.resource_address() {
-            assert!(input.amount() >= data.size,
-              "insufficient size");
-
-            self.exrd_vault.put( input );
-          } else {
-            assert!(input.amount() >= exrdxrd*data.size,
-              "insufficient size");
-
-            self.xrd_vault.put( input );
-          }
-        }
-        Some(false) => {
-          if input.resource_address() == self.exrd_vault.resource_address() {   
-            assert!(exrdxrd*input.amount() >= data.size,
-              "insufficient size");
-
-            self.exrd_vault.put(input);
-          } else {
-            assert!(input.amount() >= data.size,
-              "insufficient size");
-
-            self.xrd_vault.put(input);
-          }
-        }
-        None => panic!("wrong resource type")
+      let rate = match data.isloan {
+        Some(true) => if repaid_exrd { dec!(1) } else { exrdxrd },
+        Some(false) => if repaid_exrd { dec!(1) / exrdxrd } else { dec!(1) },
+        None => panic!("wrong flash type")
       };
+      assert!(input.amount() >= (data.principal + data.fee) * rate,
+        "insufficient size");
+
+      let principal = input.take(data.principal * rate);
+      if repaid_exrd {
+        self.exrd_vault.put(principal);
+      } else {
+        self.xrd_vault.put(principal);
+      }
 
       self.fl_active = false;
       self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
         ResourceManager::from(self.flash_resource).burn(flash)
-      })
+      });
+      self.flash_fee_out(input);
     }
 
-    // TODO: impose limit on the size?
-    // what could go wrong? lmao
     pub fn flash_mint_start(&mut self, size: Decimal) -> (Bucket, Bucket) {
       assert!( !self.stopped && !self.power_usd.is_empty(),
         "USD stopped or empty"); 
+      assert!( !self.tripped,
+        "circuit breaker tripped");
+      assert!( size <= self.flash_max.0,
+        "over the flash mint limit");
       // the liablitity # doesn't change until repayment
       assert!(!self.fm_active, 
         "twice flash minted");
@@ -992,7 +3655,8 @@ mod usd {
         let flash = ResourceManager::from(self.flash_resource)
           .mint_ruid_non_fungible(
             Flash {
-              size: size*self.flash_fee,
+              principal: size,
+              fee: size * (self.flash_fee - dec!(1)),
               isloan: None
           });
 
@@ -1002,14 +3666,16 @@ mod usd {
       })
     }
 
-    pub fn flash_mint_end(&mut self, input: Bucket, flash: Bucket) {
+    // the principal is burned, the fee (and anything over it) goes to Delta
+    // liabilities are untouched, they never changed
+    pub fn flash_mint_end(&mut self, mut input: Bucket, flash: Bucket) {
       assert!(self.fm_active, 
         "twice flash minted");
       assert!(flash.resource_address() == self.flash_resource,
         "not flash");
 
       let data: Flash = flash.as_non_fungible().non_fungible().data();
-      assert!(input.amount() >= data.size,
+      assert!(input.amount() >= data.principal + data.fee,
         "insufficient size");
       assert!(input.resource_address() == self.eusd_resource,
         "wrong resource");
@@ -1020,12 +3686,55 @@ mod usd {
       };
 
       self.fm_active = false;
-      self.liabilities_total -= input.amount() * (dec!(1) - self.flash_fee);
 
+      let principal = input.take(data.principal);
       self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
-        ResourceManager::from(self.eusd_resource).burn(input);
+        ResourceManager::from(self.eusd_resource).burn(principal);
         ResourceManager::from(self.flash_resource).burn(flash);
       });
+      self.flash_fee_out(input);
+    }
+
+    // flash through a callback, in one call
+    // component.method(Bucket, owed: Decimal) -> Bucket gets the flashed bucket 
+    // and the principal + fee it has to hand back, in the same resource
+    // isloan as in Flash: None -- EUSD mint, Some(true) -- EXRD, Some(false) -- XRD
+    // the callee can't call back into Usd, which includes swapping on Eux
+    pub fn flash(&mut self, size: Decimal, isloan: Option<bool>, 
+      component: ComponentAddress, method: String) {
+      let (out, flash) = match isloan {
+        Some(res) => self.flash_loan_start(size, res),
+        None => self.flash_mint_start(size)
+      };
+      let data: Flash = flash.as_non_fungible().non_fungible().data();
+
+      let callee: Global<AnyComponent> = component.into();
+      let repaid = callee.call_raw::<Bucket>(
+        &method, scrypto_args!(out, data.principal + data.fee));
+
+      match isloan {
+        Some(_) => self.flash_loan_end(repaid, flash),
+        None => self.flash_mint_end(repaid, flash)
+      };
+    }
+
+    // EUSD mint, XRD worth loan
+    pub fn get_flash_max(&self) -> (Decimal, Decimal) {
+      self.flash_max
+    }
+
+    pub fn set_flash_max(&mut self, mint_max: Decimal, loan_max: Decimal) {
+      assert!( mint_max >= dec!(0) && loan_max >= dec!(0),
+        "negative flash limit");
+      self.flash_max = (mint_max, loan_max);
+    }
+
+    fn flash_fee_out(&self, fee: Bucket) {
+      if fee.is_empty() {
+        fee.drop_empty();
+      } else {
+        self.delta().call_raw::<()>("deposit", scrypto_args!(fee));
+      }
     }
 
     // Automatic Arbitrage / Mandatory Pegging parts
@@ -1033,18 +3742,52 @@ mod usd {
     // check if aa is necessary
     // contains all the mandatory pegging logic
     // for v2, to maybe AMO-ize that
-    // spot is EUSD/EXRD
+    // spot is EUSD/EXRD, only taken from Eux as it can trip the breaker
     pub fn aa_poke(&mut self, spot: Decimal) -> Option<(Decimal, Decimal, bool)> {
       info!("aa_poke IN"); 
       // todo panic if flashed
       // ^ is it even needed? shouldn't be a problem really
 
-      assert!( !self.fl_active && !self.fm_active,
-        "can't liquidate during flash transactions");
+      // no AA under a flash, flashed funds can't trade against it
+      // the swap itself still goes through, see leverage
+      if self.fl_active || self.fm_active || self.tripped {
+        return None
+      }
 
       // EUSD/EXRD
       let usdexrd = dec!(1) / self.guarded_get_rescaled_oracle().expect("OUTDATED ORACLE");
 
+      // no AA into a spot this far off, something's broken
+      if self.off_spot(spot, usdexrd) {
+        self.trip_breaker("spot");
+        return None
+      }
+
+      self.aa_target(spot, usdexrd)
+    }
+
+    // aa_poke without tripping anything, for the DEX to quote swaps with
+    pub fn aa_peek(&self, spot: Decimal) -> Option<(Decimal, Decimal, bool)> {
+      if self.fl_active || self.fm_active || self.tripped {
+        return None
+      }
+
+      let usdexrd = dec!(1) / self.guarded_get_rescaled_oracle().expect("OUTDATED ORACLE");
+
+      // aa_poke would trip, and not AA
+      if self.off_spot(spot, usdexrd) {
+        return None
+      }
+
+      self.aa_target(spot, usdexrd)
+    }
+
+    fn off_spot(&self, spot: Decimal, usdexrd: Decimal) -> bool {
+      spot > usdexrd * (dec!(1) + self.breaker.1) || spot < usdexrd * (dec!(1) - self.breaker.1)
+    }
+
+    // (target, oracle, direction) if the spot is out of the bounds
+    fn aa_target(&self, spot: Decimal, usdexrd: Decimal) -> Option<(Decimal, Decimal, bool)> {
       if spot > usdexrd * self.upper_bound {
         Some((usdexrd * self.upper_bound, usdexrd, true))
       } else if spot < usdexrd * self.lower_bound {
@@ -1054,12 +3797,28 @@ mod usd {
       }
     }
 
-    // execute aa
-    pub fn aa_woke(&mut self, size: Decimal, direction: bool) 
-      -> Option<Bucket> {
-      info!("aa_woke IN"); 
+    // Eux pushes its TWAP (EUSD/EXRD) at most once a minute
+    // pushed rather than pulled, as Usd can't call Eux while Eux is calling it
+    pub fn twap_poke(&mut self, twap: Decimal) {
+      self.eux_twap = (twap, Clock::current_time_rounded_to_minutes());
+    }
+
+    // how much would aa_woke hand out for a given size, None if nothing
+    // separate so that the DEX can quote swaps that include the AA
+    pub fn aa_size(&mut self, size: Decimal, direction: bool) -> Option<Decimal> {
       let (tcr, au, lu) = self.tcr_au_lu();
+      self.aa_room(size, direction, tcr, au, lu)
+    }
+
+    // aa_size without accruing, so as of the last accrual
+    pub fn aa_size_view(&self, size: Decimal, direction: bool) -> Option<Decimal> {
+      let xrdusd = self.guarded_get_oracle().expect("OUTDATED ORACLE");
+      let au = self.assets_xrd() * xrdusd;
+      self.aa_room(size, direction, au / self.liabilities_total, au, self.liabilities_total)
+    }
 
+    fn aa_room(&self, size: Decimal, direction: bool, 
+      tcr: Decimal, au: Decimal, lu: Decimal) -> Option<Decimal> {
       if direction {
         // above backstop, can do MPup
         if tcr > self.bp {
@@ -1067,8 +3826,9 @@ mod usd {
           // bp <= a / (l + mint)
           let mut max_mint = (au - lu * self.bp) / self.bp;
           // pick smaller out of the CR limit and the static size limit
-          max_mint = if max_mint > self.maximum_minted - self.liabilities_total {
-              self.maximum_minted - self.liabilities_total 
+          let room = self.maximum_minted - self.liabilities_total - self.iso_liabilities_total;
+          max_mint = if max_mint > room {
+              room
             } else {
               max_mint
             };
@@ -1077,44 +3837,50 @@ mod usd {
           assert!( mint < au && mint < lu,
             "incoherence" );
 
-          self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
-            self.liabilities_total += mint;
-            Some(ResourceManager::from(self.eusd_resource).mint(mint))
-          })
-        } else {
-          None
+          if mint > dec!(0) {
+            return Some(mint)
+          }
         }
       } else {
-        let exrdxrd = self.exrdxrd();
         // above emergency, can do MPdown
         if tcr > self.ep {
+          // either enough EXRD outright, or enough XRD to stake for the rest
+          // otherwise (protocol is broke lol) no good option here
+          // TODO: maybe halt the system?
+          if self.exrd_vault.amount() >= size || 
+            (size - self.exrd_vault.amount()) * self.exrdxrd() <= self.xrd_vault.amount() {
+            return Some(size)
+          }
+        }
+      }
+      None
+    }
+
+    // execute aa
+    pub fn aa_woke(&mut self, size: Decimal, direction: bool) 
+      -> Option<Bucket> {
+      info!("aa_woke IN"); 
+      if let Some(size) = self.aa_size(size, direction) {
+        if direction {
+          self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {
+            self.liabilities_total += size;
+            Some(ResourceManager::from(self.eusd_resource).mint(size))
+          })
+        } else {
+          let exrdxrd = self.exrdxrd();
           self.power_usd.as_fungible().authorize_with_amount(dec!(1), || {            
-            if self.exrd_vault.amount() <= size {
-              let valid: Global<AnyComponent> = self.exrd_validator.into();
-              let diff = size - self.exrd_vault.amount();
-              let reqxrd = diff * dec!(1) / exrdxrd;
-
-              if reqxrd >= self.xrd_vault.amount() {
-                let newexrd = valid.call_raw(
-                  "stake",
-                  scrypto_args!(self.xrd_vault.take(reqxrd))
-                );
-                self.exrd_vault.put(newexrd);
-
-                Some(self.exrd_vault.take(size))
-              } else {
-                // (protocol is broke lol)
-                // returnning None, no good option here
-                // TODO: maybe halt the system?
-                None
-              }
-            } else {
-              Some(self.exrd_vault.take(size))
+            if self.exrd_vault.amount() < size {
+              // EXRD missing, priced in XRD
+              let reqxrd = (size - self.exrd_vault.amount()) * exrdxrd;
+
+              let newexrd = self.validator().stake(self.xrd_vault.take(reqxrd));
+              self.exrd_vault.put(newexrd);
             }
+            Some(self.exrd_vault.take(size))
           })
-        } else {
-          None
         }
+      } else {
+        None
       }
     }
     
@@ -1123,6 +3889,7 @@ mod usd {
     // remainder is of type dep on direction -- incoherence panics
     pub fn aa_choke(&mut self, ret: Bucket, profit: Bucket, direction: bool) {
       info!("aa_choke IN"); 
+      self.accrue();
 
       info!("stage 1");
       let alpha: Global<AnyComponent> = self.alpha_addr.into();
@@ -1151,7 +3918,7 @@ mod usd {
     // internal 
 
     // returns EXRD/USD
-    pub fn guarded_get_rescaled_oracle(&mut self) -> Option<Decimal> {
+    pub fn guarded_get_rescaled_oracle(&self) -> Option<Decimal> {
       if let Some(xrdusd) = self.guarded_get_oracle() {
         // EXRD/USD = XRD/USD * EXRD/XRD 
         Some(xrdusd * self.exrdxrd())
@@ -1161,46 +3928,216 @@ mod usd {
     }
 
     // if feed is outdated, stop the system / withdrawal only mode
+    // walks the sources in order, the first fresh one is the price
+    // and the next fresh one, if any, has to agree with it within the cross deviation
+    // a Component source that panics takes this down with it, see OracleSource
     pub fn guarded_get_oracle(&self) -> Option<Decimal> {
-      let (xrdusd, last_update) = self.get_oracle();
+      let mut fresh = self.oracle_sources.iter()
+        .filter_map(|source| self.oracle_source_price(source));
+
+      let xrdusd = fresh.next()?;
+      if let Some(check) = fresh.next() {
+        if check > xrdusd * (dec!(1) + self.oracle_cross_deviation) 
+          || check < xrdusd * (dec!(1) - self.oracle_cross_deviation) {
+          return None
+        }
+      }
+
+      Some(xrdusd)
+    }
+
+    // None if outdated
+    fn oracle_source_price(&self, source: &OracleSource) -> Option<Decimal> {
+      let (xrdusd, last_update) = match source {
+        OracleSource::Feeders => self.get_oracle(),
+        OracleSource::Component(component, method) => {
+          let c: Global<AnyComponent> = (*component).into();
+          c.call_raw::<(Decimal, Instant)>(method, scrypto_args!())
+        },
+        OracleSource::EuxTwap => {
+          let (twap, when) = self.eux_twap;
+          if twap <= dec!(0) {
+            return None
+          }
+          // XRD/USD = 1 / (EXRD/EUSD * XRD/EXRD)
+          (dec!(1) / (twap * self.exrdxrd()), when)
+        }
+      };
 
-      // if oracle inactive for 5 minutes, shit the bed
-      if last_update.add_minutes(5i64).expect("incoherence").compare(
+      // if oracle inactive for the staleness window, shit the bed
+      if last_update.add_minutes(self.oracle_staleness).expect("incoherence").compare(
           Clock::current_time_rounded_to_minutes(),
           TimeComparisonOperator::Lte
-       ) {
+       ) || xrdusd <= dec!(0) {
         None
       } else {
         Some(xrdusd)
       }
     }
 
+    // any registered feeder can post, the stored price is the median
+    // of all the fresh posts, moved at most by max deviation per staleness window
+    // a post outside of the absolute bounds is rejected
+    // a median moving past the quarantine is held until a different feeder confirms it, 
+    // posting within the quarantine of it before it's stale
     pub fn set_oracle(&mut self, exch: Decimal, p: Proof) {
+      let feeder = p.resource_address();
+      assert!( self.oracle_feeds.contains_key(&feeder),
+        "wrong call");
+      let p = p.check(feeder);
+      assert!( p.amount() >= dec!(1),
+        "wrong call");
+
+      assert!( exch > dec!(0),
+        "nonpositive price");
+      assert!( exch >= self.oracle_bounds.0 && exch <= self.oracle_bounds.1,
+        "price out of bounds");
+
+      let now = Clock::current_time_rounded_to_minutes();
+      self.oracle_feeds.insert(feeder, (exch, now));
+
+      // the post above is fresh, so there's always at least one
+      let median = self.oracle_median().expect("incoherence");
+
+      // the clamp is against the price at the start of the window, not the last update
+      // otherwise a run of posts walks it max deviation at a time
+      if self.oracle_anchor.1.add_minutes(self.oracle_staleness).expect("incoherence")
+          .compare(now, TimeComparisonOperator::Lte) {
+        self.oracle_anchor = (self.oracle, now);
+      }
+      let lower = self.oracle_anchor.0 * (dec!(1) - self.oracle_max_deviation);
+      let upper = self.oracle_anchor.0 * (dec!(1) + self.oracle_max_deviation);
+      let new = if median < lower {
+          lower
+        } else if median > upper {
+          upper
+        } else {
+          median
+        };
 
-      Runtime::emit_event(OracleEvent { old: self.oracle, new: exch });
+      if new > self.oracle * (dec!(1) + self.oracle_quarantine) 
+        || new < self.oracle * (dec!(1) - self.oracle_quarantine) {
+        let confirmed = match self.oracle_pending {
+          Some((pending, when, by)) => 
+            by != feeder
+            && when.add_minutes(self.oracle_staleness).expect("incoherence")
+              .compare(now, TimeComparisonOperator::Gt)
+            && new <= pending * (dec!(1) + self.oracle_quarantine)
+            && new >= pending * (dec!(1) - self.oracle_quarantine),
+          None => false
+        };
 
-      if p.resource_address() == self.oracle1 {
-        self.oracle = exch;
-        return
+        if !confirmed {
+          Runtime::emit_event(OracleQuarantineEvent { old: self.oracle, pending: new });
+          self.oracle_pending = Some((new, now, feeder));
+          return
+        }
       }
+      self.oracle_pending = None;
 
-      // if after 30m since last update, backup can post
-      let second_allowed = Clock::current_time_is_strictly_after( 
-        self.oracle_timestamp.add_minutes(30i64).unwrap(), 
-        TimePrecision::Minute );
-
-      if second_allowed && p.resource_address() == self.oracle2 {
-        self.oracle = exch;
-        return
+      // only on a confirmed price, a lone quarantined post can't trip it
+      if new > self.oracle * (dec!(1) + self.breaker.0) 
+        || new < self.oracle * (dec!(1) - self.breaker.0) {
+        self.trip_breaker("oracle");
       }
 
-      panic!("wrong call")
+      Runtime::emit_event(OracleEvent { old: self.oracle, new });
+
+      self.oracle = new;
+      self.oracle_timestamp = now;
+
+      self.check_breaker();
     }
 
     // XRD/USD and last time it was updated
     pub fn get_oracle(&self) -> (Decimal, Instant) {
-      // TODO once real oracle is running, use the self timestamp
-      (self.oracle, Clock::current_time_rounded_to_minutes())
+      (self.oracle, self.oracle_timestamp)
+    }
+
+    // median of the posts younger than the staleness window
+    fn oracle_median(&self) -> Option<Decimal> {
+      let now = Clock::current_time_rounded_to_minutes();
+
+      let mut fresh: Vec<Decimal> = self.oracle_feeds.values()
+        .filter(|(_, when)| 
+          when.add_minutes(self.oracle_staleness).expect("incoherence")
+            .compare(now, TimeComparisonOperator::Gt))
+        .map(|(price, _)| *price)
+        .collect();
+
+      if fresh.is_empty() {
+        return None
+      }
+
+      fresh.sort();
+      let mid = fresh.len() / 2;
+      if fresh.len() % 2 == 0 {
+        Some((fresh[mid - 1] + fresh[mid]) / dec!(2))
+      } else {
+        Some(fresh[mid])
+      }
+    }
+
+    // feeds, staleness, max deviation, quarantine, (floor, ceiling), pending
+    pub fn get_oracle_params(&self) 
+      -> (IndexMap<ResourceAddress, (Decimal, Instant)>, i64, Decimal, Decimal, (Decimal, Decimal),
+        Option<(Decimal, Instant, ResourceAddress)>) {
+      (
+        self.oracle_feeds.clone(),
+        self.oracle_staleness,
+        self.oracle_max_deviation,
+        self.oracle_quarantine,
+        self.oracle_bounds,
+        self.oracle_pending
+      )
+    }
+
+    pub fn set_oracle_params(&mut self, staleness: i64, max_deviation: Decimal, 
+      quarantine: Decimal, floor: Decimal, ceiling: Decimal) {
+      assert!( staleness > 0i64, 
+        "nonpositive staleness");
+      assert!( max_deviation > dec!(0) && max_deviation < dec!(1),
+        "deviation out of bounds");
+      assert!( quarantine > dec!(0) && quarantine <= max_deviation,
+        "quarantine out of bounds");
+      assert!( max_deviation > self.breaker.0,
+        "max deviation under the breaker oracle move");
+      assert!( floor > dec!(0) && floor < ceiling,
+        "price bounds out of bounds");
+
+      self.oracle_staleness = staleness;
+      self.oracle_max_deviation = max_deviation;
+      self.oracle_quarantine = quarantine;
+      self.oracle_bounds = (floor, ceiling);
+    }
+
+    pub fn get_oracle_sources(&self) -> (Vec<OracleSource>, Decimal) {
+      (self.oracle_sources.clone(), self.oracle_cross_deviation)
+    }
+
+    pub fn set_oracle_sources(&mut self, sources: Vec<OracleSource>, cross_deviation: Decimal) {
+      assert!( !sources.is_empty(),
+        "no oracle sources");
+      assert!( cross_deviation > dec!(0) && cross_deviation < dec!(1),
+        "deviation out of bounds");
+
+      self.oracle_sources = sources;
+      self.oracle_cross_deviation = cross_deviation;
+    }
+
+    pub fn add_oracle_feeder(&mut self, feeder: ResourceAddress) {
+      assert!( !self.oracle_feeds.contains_key(&feeder),
+        "feeder already registered");
+
+      self.oracle_feeds.insert(feeder, (dec!(0), Instant::new(0i64)));
+    }
+
+    // the last one can't go, there would be no way to ever update the price
+    pub fn remove_oracle_feeder(&mut self, feeder: ResourceAddress) {
+      assert!( self.oracle_feeds.len() > 1,
+        "last feeder");
+
+      self.oracle_feeds.shift_remove(&feeder).expect("feeder not registered");
     }
   }
 }
\ No newline at end of file
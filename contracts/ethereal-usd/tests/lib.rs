@@ -1,47 +1,1415 @@
+use radix_engine::transaction::TransactionReceipt;
 use scrypto::prelude::*;
 use scrypto_unit::*;
 use transaction::builder::ManifestBuilder;
+use transaction::prelude::*;
+
+// a started Usd with a first ECDP, an XRD/USD of 1 and no flash fee
+// the flash fee would go to Delta, which takes a whole deployment to reach
+// with_eux adds Alpha, for Usd and Eux to find each other, and a seeded Eux
+struct Env {
+    runner: DefaultTestRunner,
+    public_key: Secp256k1PublicKey,
+    account: ComponentAddress,
+    package: PackageAddress,
+    power_azero: ResourceAddress,
+    oracle1: ResourceAddress,
+    oracle2: ResourceAddress,
+    usd: ComponentAddress,
+    eux: ComponentAddress,
+    eusd: ResourceAddress,
+    exrd: ResourceAddress,
+    ecdp: ResourceAddress,
+    manager: ResourceAddress,
+    flash: ResourceAddress,
+    first: NonFungibleLocalId,
+    round: u64,
+    now_ms: i64,
+}
+
+// Usd's events and args, as far as the tests read or pass them
+#[derive(ScryptoSbor)]
+struct NewEcdpEvent {
+    ecdp: NonFungibleLocalId,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone)]
+struct EcdpManagerRights {
+    repay: bool,
+    cr_floor: Option<Decimal>,
+}
+
+#[derive(ScryptoSbor)]
+struct EcdpManagerEvent {
+    #[allow(dead_code)]
+    ecdp: NonFungibleLocalId,
+    manager: NonFungibleLocalId,
+    #[allow(dead_code)]
+    rights: Option<EcdpManagerRights>,
+}
+
+#[derive(ManifestSbor)]
+struct ProtectionRule {
+    repay_under: Option<(Decimal, Decimal)>,
+    boost_over: Option<(Decimal, Decimal)>,
+}
+
+#[derive(ManifestSbor)]
+#[allow(dead_code)]
+enum OracleSource {
+    Feeders,
+    Component(ComponentAddress, String),
+    EuxTwap,
+}
+
+#[derive(ManifestSbor)]
+#[allow(dead_code)]
+enum PriceSource {
+    Lsu(ComponentAddress),
+    Component(ComponentAddress, String),
+}
+
+// equal up to the rounding of lp math
+fn assert_near(a: Decimal, b: Decimal) {
+    assert!(a - b < dec!("0.000001") && b - a < dec!("0.000001"), "{} != {}", a, b);
+}
+
+impl Env {
+    fn new() -> Self {
+        Self::build(false)
+    }
+
+    fn with_eux() -> Self {
+        Self::build(true)
+    }
+
+    fn build(with_eux: bool) -> Self {
+        let mut runner = TestRunnerBuilder::new().build();
+        let (public_key, _private_key, account) = runner.new_allocated_account();
+
+        let now_ms = 1_700_000_000_000i64;
+        runner
+            .advance_to_round_at_timestamp(Round::of(1), now_ms)
+            .expect_commit_success();
+
+        for _ in 0..3 {
+            let manifest = ManifestBuilder::new()
+                .get_free_xrd_from_faucet()
+                .deposit_batch(account)
+                .build();
+            runner
+                .execute_manifest_ignoring_fee(
+                    manifest,
+                    vec![NonFungibleGlobalId::from_public_key(&public_key)],
+                )
+                .expect_commit_success();
+        }
+
+        let power_azero = runner.create_fungible_resource(dec!(1), 0, account);
+        let power_eux = runner.create_fungible_resource(dec!(1), 0, account);
+        let power_usd = runner.create_fungible_resource(dec!(1), 0, account);
+        let oracle1 = runner.create_fungible_resource(dec!(1), 0, account);
+        let oracle2 = runner.create_fungible_resource(dec!(1), 0, account);
+
+        let package_address = runner.compile_and_publish(this_package!());
+
+        let mut env = Self {
+            runner,
+            public_key,
+            account,
+            package: package_address,
+            power_azero,
+            oracle1,
+            oracle2,
+            usd: account,
+            eux: account,
+            eusd: XRD,
+            exrd: XRD,
+            ecdp: XRD,
+            manager: XRD,
+            flash: XRD,
+            first: NonFungibleLocalId::integer(0),
+            round: 1,
+            now_ms,
+        };
+
+        // 4000 for the first ECDP, 700 for the Eux pool
+        let (validator, exrd) = env.new_lsu(dec!(4700));
+        env.exrd = exrd;
+
+        // dao, bang -- only reached by the AA, flash fees and metadata, the account stands in
+        // and so does alpha, for Usd, if there's no Eux to find
+        let alpha = if with_eux {
+            let alpha_package = env.runner.compile_and_publish(
+                concat!(env!("CARGO_MANIFEST_DIR"), "/../ethereal-alpha"));
+            let power_zero = env.runner.create_fungible_resource(dec!(1), 0, account);
+            let power_omega = env.runner.create_fungible_resource(dec!(1), 0, account);
+            let power_alpha = env.runner.create_fungible_resource(dec!(1), 0, account);
+            let receipt = env.run(
+                ManifestBuilder::new()
+                    .withdraw_from_account(account, power_alpha, dec!(1))
+                    .take_all_from_worktop(power_alpha, "power_alpha")
+                    .call_function_with_name_lookup(
+                        alpha_package,
+                        "Alpha",
+                        "from_nothing",
+                        |lookup| {
+                            (
+                                account,
+                                power_zero,
+                                power_omega,
+                                lookup.bucket("power_alpha"),
+                                power_azero,
+                                account,
+                                account,
+                                account,
+                                account,
+                            )
+                        },
+                    )
+                    .build(),
+            );
+            receipt.expect_commit_success().new_component_addresses()[0]
+        } else {
+            account
+        };
+
+        let receipt = env.run(
+            ManifestBuilder::new()
+                .withdraw_from_account(account, power_usd, dec!(1))
+                .take_all_from_worktop(power_usd, "power_usd")
+                .call_function_with_name_lookup(
+                    package_address,
+                    "Usd",
+                    "from_nothing",
+                    |lookup| {
+                        (
+                            alpha,
+                            power_azero,
+                            power_eux,
+                            lookup.bucket("power_usd"),
+                            exrd,
+                            validator,
+                            dec!("0.99"),
+                            dec!("1.01"),
+                            dec!(1),
+                            account,
+                            dec!(1),
+                            oracle1,
+                            oracle2,
+                        )
+                    },
+                )
+                .build(),
+        );
+        let commit = receipt.expect_commit_success();
+        env.usd = commit.new_component_addresses()[0];
+        let resources: Vec<ResourceAddress> =
+            commit.new_resource_addresses().iter().copied().collect();
+        env.eusd = env.resource_named(&resources, "Ethereal USD");
+        env.ecdp = env.resource_named(&resources, "Ethereal ECDP Ownership Badge");
+        env.manager = env.resource_named(&resources, "Ethereal ECDP Manager Badge");
+        env.flash = env.resource_named(&resources, "FLASHFLASHFLASHFLASH");
+
+        env.azero("start_stop", manifest_args!(false))
+            .expect_commit_success();
+
+        let receipt = env.run(
+            ManifestBuilder::new()
+                .withdraw_from_account(account, exrd, dec!(4000))
+                .take_all_from_worktop(exrd, "exrd")
+                .call_method_with_name_lookup(env.usd, "first_ecdp", |lookup| {
+                    (lookup.bucket("exrd"),)
+                })
+                .deposit_batch(account)
+                .build(),
+        );
+        env.first = env.new_ecdps(&receipt)[0].clone();
+
+        if with_eux {
+            env.seed_eux(alpha, power_eux);
+        }
+
+        env
+    }
+
+    // Eux at 700 EUSD / 700 EXRD, the oracle's price, and Alpha pointing at both
+    fn seed_eux(&mut self, alpha: ComponentAddress, power_eux: ResourceAddress) {
+        let (account, power_azero, eusd, exrd) = (self.account, self.power_azero, self.eusd, self.exrd);
+        let eux_package = self.runner.compile_and_publish(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../ethereal-dex-eux"));
+        let receipt = self.run(
+            ManifestBuilder::new()
+                .withdraw_from_account(account, power_eux, dec!(1))
+                .take_all_from_worktop(power_eux, "power_eux")
+                .call_function_with_name_lookup(
+                    eux_package,
+                    "Eux",
+                    "from_nothing",
+                    |lookup| {
+                        (
+                            alpha,
+                            power_azero,
+                            lookup.bucket("power_eux"),
+                            eusd,
+                            exrd,
+                            dec!("0.997"),
+                            account,
+                        )
+                    },
+                )
+                .build(),
+        );
+        self.eux = receipt.expect_commit_success().new_component_addresses()[0];
+
+        let manifest = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(account, self.power_azero, dec!(1))
+            .call_method(alpha, "set_app_addrs", manifest_args!((self.usd, self.eux, account)))
+            .withdraw_from_account(account, self.eusd, dec!(700))
+            .take_all_from_worktop(self.eusd, "eusd")
+            .withdraw_from_account(account, self.exrd, dec!(700))
+            .take_all_from_worktop(self.exrd, "exrd")
+            .call_method_with_name_lookup(self.eux, "first_deposit", |lookup| {
+                (lookup.bucket("eusd"), lookup.bucket("exrd"))
+            })
+            .call_method(self.eux, "start_stop", manifest_args!(false))
+            .deposit_batch(account)
+            .build();
+        self.run(manifest).expect_commit_success();
+    }
+
+    fn run(&mut self, manifest: TransactionManifestV1) -> TransactionReceipt {
+        let receipt = self.runner.execute_manifest_ignoring_fee(
+            manifest,
+            vec![NonFungibleGlobalId::from_public_key(&self.public_key)],
+        );
+        println!("{:?}\n", receipt);
+        receipt
+    }
+
+    // a call under the azero role
+    fn azero(&mut self, method: &str, args: ManifestArgs) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(self.account, self.power_azero, dec!(1))
+            .call_method(self.usd, method, args)
+            .build();
+        self.run(manifest)
+    }
+
+    // a call with nothing to prove, the output is that of the call
+    fn call(&mut self, method: &str, args: ManifestArgs) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .call_method(self.usd, method, args)
+            .build();
+        self.run(manifest)
+    }
+
+    // same, on Eux
+    fn eux_call(&mut self, method: &str, args: ManifestArgs) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .call_method(self.eux, method, args)
+            .build();
+        self.run(manifest)
+    }
+
+    fn eux_swap(&mut self, resource: ResourceAddress, amount: Decimal) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .withdraw_from_account(self.account, resource, amount)
+            .take_all_from_worktop(resource, "input")
+            .call_method_with_name_lookup(self.eux, "swap", |lookup| {
+                (lookup.bucket("input"),)
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.run(manifest)
+    }
+
+    // proof of the ECDP put under name, for a call taking it
+    fn prove(&self, builder: ManifestBuilder, id: &NonFungibleLocalId, name: &str) -> ManifestBuilder {
+        builder
+            .create_proof_from_account_of_non_fungibles(self.account, self.ecdp, &btreeset!(id.clone()))
+            .pop_from_auth_zone(name)
+    }
+
+    // a validator staked to by its owner, and its stake units in the account
+    fn new_lsu(&mut self, xrd: Decimal) -> (ComponentAddress, ResourceAddress) {
+        let (key, _, _) = self.runner.new_allocated_account();
+        let validator = self.runner.new_validator_with_pub_key(key, self.account);
+        let owner = NonFungibleLocalId::bytes(validator.as_node_id().0).unwrap();
+
+        let manifest = ManifestBuilder::new()
+            .create_proof_from_account_of_non_fungibles(
+                self.account,
+                VALIDATOR_OWNER_BADGE,
+                &btreeset!(owner),
+            )
+            .withdraw_from_account(self.account, XRD, xrd)
+            .take_all_from_worktop(XRD, "xrd")
+            .call_method_with_name_lookup(validator, "stake_as_owner", |lookup| {
+                (lookup.bucket("xrd"),)
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.run(manifest).expect_commit_success();
+
+        let lsu = self.runner.get_validator_info(validator).stake_unit_resource;
+        (validator, lsu)
+    }
+
+    fn resource_named(&mut self, resources: &[ResourceAddress], name: &str) -> ResourceAddress {
+        *resources
+            .iter()
+            .find(|ra| {
+                match self.runner.get_metadata(GlobalAddress::from(**ra), "name") {
+                    Some(MetadataValue::String(n)) => n == name,
+                    _ => false,
+                }
+            })
+            .expect("resource not found")
+    }
+
+    fn new_ecdps(&self, receipt: &TransactionReceipt) -> Vec<NonFungibleLocalId> {
+        receipt
+            .expect_commit_success()
+            .application_events
+            .iter()
+            .filter(|(event, _)| self.runner.event_name(event) == "NewEcdpEvent")
+            .map(|(_, data)| scrypto_decode::<NewEcdpEvent>(data).unwrap().ecdp)
+            .collect()
+    }
+
+    fn emitted(&self, receipt: &TransactionReceipt, name: &str) -> bool {
+        receipt
+            .expect_commit_success()
+            .application_events
+            .iter()
+            .any(|(event, _)| self.runner.event_name(event) == name)
+    }
+
+    fn balance(&mut self, resource: ResourceAddress) -> Decimal {
+        self.runner.get_component_balance(self.account, resource)
+    }
+
+    // an ECDP holding xrd of XRD, with debt EUSD drawn against it
+    fn open(&mut self, xrd: Decimal, debt: Decimal) -> NonFungibleLocalId {
+        self.open_in(xrd, debt, false)
+    }
+
+    fn open_in(&mut self, xrd: Decimal, debt: Decimal, isolated: bool) -> NonFungibleLocalId {
+        let manifest = ManifestBuilder::new()
+            .withdraw_from_account(self.account, XRD, dec!(100))
+            .take_all_from_worktop(XRD, "fee")
+            .call_method_with_name_lookup(self.usd, "open_ecdp", |lookup| {
+                (lookup.bucket("fee"), isolated)
+            })
+            .deposit_batch(self.account)
+            .build();
+        let receipt = self.run(manifest);
+        let id = self.new_ecdps(&receipt)[0].clone();
+
+        if xrd > dec!(0) {
+            self.collateralize(&id, XRD, xrd).expect_commit_success();
+        }
+        if debt > dec!(0) {
+            self.mint(&id, debt).expect_commit_success();
+        }
+        id
+    }
+
+    fn collateralize(&mut self, id: &NonFungibleLocalId,
+        resource: ResourceAddress, amount: Decimal) -> TransactionReceipt {
+        let manifest = self
+            .prove(ManifestBuilder::new(), id, "ecdp")
+            .withdraw_from_account(self.account, resource, amount)
+            .take_all_from_worktop(resource, "input")
+            .call_method_with_name_lookup(self.usd, "ecdp_collateralize", |lookup| {
+                (lookup.bucket("input"), lookup.proof("ecdp"))
+            })
+            .build();
+        self.run(manifest)
+    }
+
+    fn mint(&mut self, id: &NonFungibleLocalId, size: Decimal) -> TransactionReceipt {
+        let manifest = self
+            .prove(ManifestBuilder::new(), id, "ecdp")
+            .call_method_with_name_lookup(self.usd, "ecdp_mint_usd", |lookup| {
+                (size, lookup.proof("ecdp"))
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.run(manifest)
+    }
+
+    fn close(&mut self, id: &NonFungibleLocalId, repay: Decimal) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .withdraw_non_fungibles_from_account(self.account, self.ecdp, &btreeset!(id.clone()))
+            .take_all_from_worktop(self.ecdp, "ecdp")
+            .withdraw_from_account(self.account, self.eusd, repay)
+            .take_all_from_worktop(self.eusd, "eusd")
+            .call_method_with_name_lookup(self.usd, "close_ecdp", |lookup| {
+                (lookup.bucket("ecdp"), lookup.bucket("eusd"))
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.run(manifest)
+    }
+
+    // minutes on, with the price posted again so it isn't stale
+    fn advance(&mut self, minutes: i64) {
+        self.round += 1;
+        self.now_ms += minutes * 60_000;
+        self.runner
+            .advance_to_round_at_timestamp(Round::of(self.round), self.now_ms)
+            .expect_commit_success();
+    }
+
+    fn post_oracle(&mut self, price: Decimal) -> TransactionReceipt {
+        let feeder = self.oracle1;
+        self.post_oracle_as(feeder, price)
+    }
+
+    fn post_oracle_as(&mut self, feeder: ResourceAddress, price: Decimal) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(self.account, feeder, dec!(1))
+            .pop_from_auth_zone("feeder")
+            .call_method_with_name_lookup(self.usd, "set_oracle", |lookup| {
+                (price, lookup.proof("feeder"))
+            })
+            .build();
+        self.run(manifest)
+    }
+
+    fn liquidate(&mut self, id: &NonFungibleLocalId) -> TransactionReceipt {
+        let first = self.first.clone();
+        self.call("liquidate", manifest_args!(id.clone(), first))
+    }
+
+    // the other params at their defaults, bar the flash fee
+    fn set_mcr(&mut self, mcr: Decimal, bp: Decimal) -> TransactionReceipt {
+        self.set_params(mcr, bp, dec!(0))
+    }
+
+    fn set_borrow_rate(&mut self, br: Decimal) -> TransactionReceipt {
+        self.set_params(dec!("1.5"), dec!("1.7"), br)
+    }
+
+    fn set_params(&mut self, mcr: Decimal, bp: Decimal, br: Decimal) -> TransactionReceipt {
+        self.azero(
+            "set_params",
+            manifest_args!(
+                dec!("1.2"), mcr, bp, dec!("0.99"), dec!("1.01"), dec!(1000000),
+                dec!(1), br, dec!("0.005"), dec!(0), dec!("0.01"), dec!(30)
+            ),
+        )
+    }
+
+    // (assets, debt) in USD
+    fn look(&mut self, id: &NonFungibleLocalId) -> (Decimal, Decimal) {
+        self.call("ecdp_look", manifest_args!(id.clone()))
+            .expect_commit_success()
+            .output(0)
+    }
+
+    // a manager badge for the ECDP, left in the account
+    fn add_manager(&mut self, id: &NonFungibleLocalId, rights: EcdpManagerRights) -> NonFungibleLocalId {
+        let manifest = self
+            .prove(ManifestBuilder::new(), id, "ecdp")
+            .call_method_with_name_lookup(self.usd, "ecdp_add_manager", |lookup| {
+                (rights, lookup.proof("ecdp"))
+            })
+            .deposit_batch(self.account)
+            .build();
+        let receipt = self.run(manifest);
+        receipt
+            .expect_commit_success()
+            .application_events
+            .iter()
+            .filter(|(event, _)| self.runner.event_name(event) == "EcdpManagerEvent")
+            .map(|(_, data)| scrypto_decode::<EcdpManagerEvent>(data).unwrap().manager)
+            .next()
+            .expect("no manager event")
+    }
+
+    // a call with the manager's proof as its last argument
+    fn as_manager(&mut self, manager: &NonFungibleLocalId, method: &str, 
+        size: Decimal) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .create_proof_from_account_of_non_fungibles(
+                self.account,
+                self.manager,
+                &btreeset!(manager.clone()),
+            )
+            .pop_from_auth_zone("manager")
+            .call_method_with_name_lookup(self.usd, method, |lookup| {
+                (size, lookup.proof("manager"))
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.run(manifest)
+    }
+
+    fn deposit_of(&mut self, id: &NonFungibleLocalId) -> Decimal {
+        self.call("deposit_of", manifest_args!(id.clone()))
+            .expect_commit_success()
+            .output(0)
+    }
+}
+
+#[test]
+fn test_split_and_close_refund_one_deposit_each() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(1000), dec!(300));
+
+    // a split is an open, it pays the open fee
+    let split = |env: &Env, fee: Decimal| {
+        env.prove(ManifestBuilder::new(), &alice, "ecdp")
+            .withdraw_from_account(env.account, XRD, fee)
+            .take_all_from_worktop(XRD, "fee")
+            .call_method_with_name_lookup(env.usd, "ecdp_split", |lookup| {
+                (lookup.proof("ecdp"), lookup.bucket("fee"), dec!("0.5"), dec!("0.5"))
+            })
+            .deposit_batch(env.account)
+            .build()
+    };
+    let manifest = split(&env, dec!(10));
+    env.run(manifest).expect_commit_failure();
+
+    let manifest = split(&env, dec!(100));
+    let receipt = env.run(manifest);
+    let half = env.new_ecdps(&receipt)[0].clone();
+    assert_eq!(env.deposit_of(&half), dec!(50));
+    assert_eq!(env.deposit_of(&alice), dec!(50));
+
+    // the pool pays the collateral back in EXRD while it has it
+    // so all the XRD coming back is the two deposits, and nothing past them
+    let xrd_before = env.balance(XRD);
+    env.close(&half, dec!(150)).expect_commit_success();
+    env.close(&alice, dec!(150)).expect_commit_success();
+    assert_eq!(env.balance(XRD) - xrd_before, dec!(100));
+
+    assert_eq!(env.deposit_of(&half), dec!(0));
+    assert_eq!(env.deposit_of(&alice), dec!(0));
+    assert_eq!(env.balance(env.ecdp), dec!(1));
+}
+
+#[test]
+fn test_asset_withdrawal_at_full_price() {
+    let mut env = Env::new();
+    let (validator, lsu) = env.new_lsu(dec!(1000));
+    env.azero(
+        "add_collateral",
+        manifest_args!(lsu, PriceSource::Lsu(validator), dec!("0.5"), dec!(1000000)),
+    )
+    .expect_commit_success();
+
+    let alice = env.open(dec!(0), dec!(0));
+    env.collateralize(&alice, lsu, dec!(1000)).expect_commit_success();
+
+    // counted at half, the ECDP holds 500 XRD of assets
+    // taken out at full, as otherwise XRD could be swapped for the units at a discount
+    let withdraw = |env: &Env, amount: Decimal| {
+        env.prove(ManifestBuilder::new(), &alice, "ecdp")
+            .call_method_with_name_lookup(env.usd, "ecdp_uncollateralize_asset", |lookup| {
+                (lsu, amount, lookup.proof("ecdp"))
+            })
+            .deposit_batch(env.account)
+            .build()
+    };
+    let manifest = withdraw(&env, dec!(1000));
+    env.run(manifest).expect_commit_failure();
+    let manifest = withdraw(&env, dec!(400));
+    env.run(manifest).expect_commit_success();
+    assert_eq!(env.balance(lsu), dec!(400));
+
+    let (assets_usd, _): (Decimal, Decimal) = env
+        .call("ecdp_look", manifest_args!(alice.clone()))
+        .expect_commit_success()
+        .output(0);
+    assert!(assets_usd > dec!(99) && assets_usd < dec!(101));
+}
+
+#[test]
+fn test_liquidation_skips_a_failing_hook_after_the_grace() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(1000), dec!(500));
+
+    // the account has no such method, so the hook always panics
+    let manifest = env
+        .prove(ManifestBuilder::new(), &alice, "ecdp")
+        .call_method_with_name_lookup(env.usd, "ecdp_set_hook", |lookup| {
+            (Some((env.account, "no_such_hook".to_owned())), lookup.proof("ecdp"))
+        })
+        .build();
+    env.run(manifest).expect_commit_success();
+
+    // mcr over alice's CR of ~2, bp over it so the TCR stays out of recovery
+    env.set_mcr(dec!(3), dec!("3.2")).expect_commit_success();
+
+    // the hook blocks it, before the notice and within its grace
+    env.liquidate(&alice).expect_commit_failure();
+    env.call("liquidation_notice", manifest_args!(alice.clone()))
+        .expect_commit_success();
+    env.advance(5);
+    env.post_oracle(dec!(1)).expect_commit_success();
+    env.liquidate(&alice).expect_commit_failure();
+
+    // past it the hook is skipped
+    env.advance(6);
+    env.post_oracle(dec!(1)).expect_commit_success();
+    let receipt = env.liquidate(&alice);
+    assert!(env.emitted(&receipt, "EcdpLiquidatedEvent"));
+
+    let (_, debt_usd): (Decimal, Decimal) = env
+        .call("ecdp_look", manifest_args!(alice.clone()))
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(debt_usd, dec!(0));
+}
 
 #[test]
-fn test_hello() {
-    // Setup the environment
-    let mut test_runner = TestRunner::builder().build();
+fn test_breaker_trip_and_untrip() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(1000), dec!(100));
+
+    // tripped, repaying and collateralizing go through, drawing doesn't
+    env.azero("trip", manifest_args!()).expect_commit_success();
+    env.mint(&alice, dec!(10)).expect_commit_failure();
+    env.collateralize(&alice, XRD, dec!(10)).expect_commit_success();
+
+    env.azero("untrip", manifest_args!()).expect_commit_success();
+    env.mint(&alice, dec!(10)).expect_commit_success();
+
+    // a stale price doesn't trip it, nor make the check revert
+    env.advance(10);
+    let tripped: bool = env
+        .call("check_breaker", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert!(!tripped);
 
-    // Create an account
-    let (public_key, _private_key, account) = test_runner.new_allocated_account();
+    // the breaker's oracle move can't be set past what the clamp lets through
+    env.azero(
+        "set_breaker",
+        manifest_args!(dec!("0.1"), dec!("0.25"), dec!("1.1")),
+    )
+    .expect_commit_failure();
+
+    // a 9% step is inside the clamp but past the 8% of the breaker
+    // quarantined on the first post, confirmed by the second feeder
+    env.post_oracle(dec!(1)).expect_commit_success();
+    env.post_oracle(dec!("0.91")).expect_commit_success();
+    let (tripped, _): (bool, (Decimal, Decimal, Decimal)) = env
+        .call("get_breaker", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert!(!tripped);
+    let oracle2 = env.oracle2;
+    env.post_oracle_as(oracle2, dec!("0.91")).expect_commit_success();
+    let (tripped, _): (bool, (Decimal, Decimal, Decimal)) = env
+        .call("get_breaker", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert!(tripped);
+}
+
+#[test]
+fn test_flash_repayment() {
+    let mut env = Env::new();
+    let eusd_before = env.balance(env.eusd);
 
-    // Publish package
-    let package_address = test_runner.compile_and_publish(this_package!());
+    // minted, handed back in full and burned
+    let flash_mint = |env: &Env, repay: Decimal| {
+        ManifestBuilder::new()
+            .call_method(env.usd, "flash_mint_start", manifest_args!(dec!(100)))
+            .take_from_worktop(env.eusd, repay, "eusd")
+            .take_all_from_worktop(env.flash, "flash")
+            .call_method_with_name_lookup(env.usd, "flash_mint_end", |lookup| {
+                (lookup.bucket("eusd"), lookup.bucket("flash"))
+            })
+            .deposit_batch(env.account)
+            .build()
+    };
+    let manifest = flash_mint(&env, dec!(100));
+    env.run(manifest).expect_commit_success();
+    assert_eq!(env.balance(env.eusd), eusd_before);
 
-    // Test the `instantiate_hello` function.
+    // short of the principal it reverts
+    let manifest = flash_mint(&env, dec!(99));
+    env.run(manifest).expect_commit_failure();
+
+    // never repaid, the flash badge can't be deposited anywhere
     let manifest = ManifestBuilder::new()
-        .call_function(
-            package_address,
-            "Hello",
-            "instantiate_hello",
-            manifest_args!(),
-        )
+        .call_method(env.usd, "flash_mint_start", manifest_args!(dec!(100)))
+        .deposit_batch(env.account)
         .build();
-    let receipt = test_runner.execute_manifest_ignoring_fee(
-        manifest,
-        vec![NonFungibleGlobalId::from_public_key(&public_key)],
-    );
-    println!("{:?}\n", receipt);
-    let component = receipt.expect_commit(true).new_component_addresses()[0];
+    env.run(manifest).expect_commit_failure();
 
-    // Test the `free_token` method.
+    // loaned XRD, repaid in XRD, out of what an ECDP put in
+    env.open(dec!(100), dec!(0));
+    let xrd_before = env.balance(XRD);
     let manifest = ManifestBuilder::new()
-        .call_method(component, "free_token", manifest_args!())
-        .call_method(
-            account,
-            "deposit_batch",
-            manifest_args!(ManifestExpression::EntireWorktop),
-        )
+        .call_method(env.usd, "flash_loan_start", manifest_args!(dec!(10), false))
+        .take_all_from_worktop(XRD, "xrd")
+        .take_all_from_worktop(env.flash, "flash")
+        .call_method_with_name_lookup(env.usd, "flash_loan_end", |lookup| {
+            (lookup.bucket("xrd"), lookup.bucket("flash"))
+        })
+        .deposit_batch(env.account)
         .build();
-    let receipt = test_runner.execute_manifest_ignoring_fee(
-        manifest,
-        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    env.run(manifest).expect_commit_success();
+    assert_eq!(env.balance(XRD), xrd_before);
+
+    // over the mint cap
+    env.azero("set_flash_max", manifest_args!(dec!(50), dec!(50)))
+        .expect_commit_success();
+    let manifest = flash_mint(&env, dec!(100));
+    env.run(manifest).expect_commit_failure();
+}
+
+#[test]
+fn test_liquidation_notice_cleared_once_back_in_range() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(1000), dec!(500));
+    let manifest = env
+        .prove(ManifestBuilder::new(), &alice, "ecdp")
+        .call_method_with_name_lookup(env.usd, "ecdp_set_hook", |lookup| {
+            (Some((env.account, "no_such_hook".to_owned())), lookup.proof("ecdp"))
+        })
+        .build();
+    env.run(manifest).expect_commit_success();
+
+    // noticed during a dip
+    env.set_mcr(dec!(3), dec!("3.2")).expect_commit_success();
+    env.call("liquidation_notice", manifest_args!(alice.clone()))
+        .expect_commit_success();
+
+    // back in range, a liquidation attempt drops the notice
+    env.set_mcr(dec!("1.5"), dec!("1.7")).expect_commit_success();
+    let receipt = env.liquidate(&alice);
+    assert!(!env.emitted(&receipt, "EcdpLiquidatedEvent"));
+
+    // so on the next dip, long after, the hook still gets its grace
+    env.advance(20);
+    env.post_oracle(dec!(1)).expect_commit_success();
+    env.set_mcr(dec!(3), dec!("3.2")).expect_commit_success();
+    env.liquidate(&alice).expect_commit_failure();
+}
+
+#[test]
+fn test_manager_rights() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(1000), dec!(100));
+
+    // a floored manager draws down to the floor, not past it
+    let floored = env.add_manager(
+        &alice,
+        EcdpManagerRights { repay: false, cr_floor: Some(dec!(2)) },
+    );
+    env.as_manager(&floored, "ecdp_withdraw_usd", dec!(700))
+        .expect_commit_success();
+    env.as_manager(&floored, "ecdp_withdraw_usd", dec!(200))
+        .expect_commit_failure();
+    env.as_manager(&floored, "ecdp_mint_usd", dec!(10))
+        .expect_commit_success();
+
+    // nothing to hold the floor against, so no draws at all
+    let bob = env.open(dec!(1000), dec!(0));
+    let drainer = env.add_manager(
+        &bob,
+        EcdpManagerRights { repay: false, cr_floor: Some(dec!(2)) },
+    );
+    env.as_manager(&drainer, "ecdp_withdraw_usd", dec!(10))
+        .expect_commit_failure();
+
+    // a repay-only manager can't draw
+    let repayer = env.add_manager(
+        &alice,
+        EcdpManagerRights { repay: true, cr_floor: None },
     );
-    println!("{:?}\n", receipt);
-    receipt.expect_commit_success();
+    env.as_manager(&repayer, "ecdp_mint_usd", dec!(1))
+        .expect_commit_failure();
+
+    // revoked, the badge is worth nothing
+    let manifest = env
+        .prove(ManifestBuilder::new(), &alice, "ecdp")
+        .call_method_with_name_lookup(env.usd, "ecdp_revoke_manager", |lookup| {
+            (floored.clone(), lookup.proof("ecdp"))
+        })
+        .build();
+    env.run(manifest).expect_commit_success();
+    env.as_manager(&floored, "ecdp_mint_usd", dec!(1))
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_oracle_median_of_fresh_feeds() {
+    let mut env = Env::new();
+    let oracle2 = env.oracle2;
+
+    // two fresh posts, the price is their median
+    env.post_oracle(dec!(1)).expect_commit_success();
+    env.post_oracle_as(oracle2, dec!("1.04")).expect_commit_success();
+    let (price, _): (Decimal, Instant) = env
+        .call("get_oracle", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(price, dec!("1.02"));
+
+    // past the staleness window the second post doesn't count
+    env.advance(6);
+    env.post_oracle(dec!("0.99")).expect_commit_success();
+    let (price, _): (Decimal, Instant) = env
+        .call("get_oracle", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(price, dec!("0.99"));
+
+    // a post that isn't a feeder's is refused
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_amount(env.account, env.power_azero, dec!(1))
+        .pop_from_auth_zone("feeder")
+        .call_method_with_name_lookup(env.usd, "set_oracle", |lookup| {
+            (dec!(1), lookup.proof("feeder"))
+        })
+        .build();
+    env.run(manifest).expect_commit_failure();
+
+    // and with no fresh post at all, nothing can be drawn
+    let alice = env.open(dec!(1000), dec!(0));
+    env.advance(6);
+    env.mint(&alice, dec!(10)).expect_commit_failure();
+}
+
+#[test]
+fn test_interest_accrues_on_debt() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(1000), dec!(100));
+
+    // over the ~31.5% a year cap
+    env.set_borrow_rate(dec!("0.00000002")).expect_commit_failure();
+    env.set_borrow_rate(dec!("0.000000001")).expect_commit_success();
+    let (_, before) = env.look(&alice);
+
+    // an hour at 1e-9 a second, the same on every ECDP's debt
+    env.advance(60);
+    env.post_oracle(dec!(1)).expect_commit_success();
+    let (_, after) = env.look(&alice);
+    let expected = before * (dec!(1) + dec!("0.000000001") * dec!(3600));
+    assert!(after - expected < dec!("0.000000001") && expected - after < dec!("0.000000001"));
+
+    // nothing accrues at a zero rate, what accrued stays
+    env.set_borrow_rate(dec!(0)).expect_commit_success();
+    env.advance(60);
+    env.post_oracle(dec!(1)).expect_commit_success();
+    let (_, later) = env.look(&alice);
+    assert_eq!(later, after);
+}
+
+#[test]
+fn test_redemption_hits_the_riskiest_first() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(1000), dec!(100));
+    let bob = env.open(dec!(300), dec!(150));
+
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account, env.eusd, dec!(100))
+        .take_all_from_worktop(env.eusd, "eusd")
+        .call_method_with_name_lookup(env.usd, "redeem", |lookup| {
+            (lookup.bucket("eusd"),)
+        })
+        .deposit_batch(env.account)
+        .build();
+    let receipt = env.run(manifest);
+    assert!(env.emitted(&receipt, "RedemptionEvent"));
+
+    // bob at 200% goes before alice at 1000%, and keeps the 0.5% fee
+    let (assets, debt) = env.look(&bob);
+    assert_near(debt, dec!(50));
+    assert_near(assets, dec!(300) - dec!("99.5"));
+    let (assets, debt) = env.look(&alice);
+    assert_near(debt, dec!(100));
+    assert_near(assets, dec!(1000));
+}
+
+#[test]
+fn test_registry_pages_from_the_lowest_cr() {
+    let mut env = Env::new();
+    let safe = env.open(dec!(1000), dec!(100));
+    let risky = env.open(dec!(200), dec!(100));
+    let middle = env.open(dec!(300), dec!(100));
+    // the first ECDP, 4000 EXRD against 777, sits between middle and safe
+    let first = env.first.clone();
+
+    let page: Vec<NonFungibleLocalId> = env
+        .call("riskiest_ecdps", manifest_args!(0u64, 2u64))
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(page, vec![risky.clone(), middle.clone()]);
+
+    let page: Vec<NonFungibleLocalId> = env
+        .call("riskiest_ecdps", manifest_args!(2u64, 2u64))
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(page, vec![first.clone(), safe.clone()]);
+
+    // refiled on a change
+    env.collateralize(&risky, XRD, dec!(2000)).expect_commit_success();
+    let page: Vec<NonFungibleLocalId> = env
+        .call("riskiest_ecdps", manifest_args!(0u64, 4u64))
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(page, vec![middle, first, safe, risky]);
+}
+
+#[test]
+fn test_liquidation_penalty_reward_and_dust() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(300), dec!(150));
+    let bob = env.open(dec!(1000), dec!(100));
+
+    // 10% penalty, 1% to the liquidator, and alice at 200% is under the raised mcr
+    env.azero(
+        "set_params",
+        manifest_args!(
+            dec!("1.2"), dec!("2.5"), dec!("2.7"), dec!("0.99"), dec!("1.01"), dec!(1000000),
+            dec!(1), dec!(0), dec!("0.005"), dec!("0.1"), dec!("0.01"), dec!(30)
+        ),
+    )
+    .expect_commit_success();
+    let receipt = env.liquidate(&alice);
+    assert!(env.emitted(&receipt, "EcdpLiquidatedEvent"));
+
+    // 300 less the 3 tip, less 165 owed, is 132 left to alice
+    // the 165 seized stays in the pool, so every asset lp is worth 5300 / 5135 more
+    let (assets, debt) = env.look(&alice);
+    assert_eq!(debt, dec!(0));
+    assert_near(assets, dec!(132) * dec!(5300) / dec!(5135));
+
+    // with no SP, the debt is spread over bob and the first ECDP's 777
+    let (_, debt) = env.look(&bob);
+    assert_near(debt, dec!(100) * dec!(1027) / dec!(877));
+
+    // carol at 500% is under the 30 USD dust threshold, so liquidatable anyway
+    let carol = env.open(dec!(25), dec!(5));
+    let receipt = env.liquidate(&carol);
+    assert!(env.emitted(&receipt, "EcdpLiquidatedEvent"));
+}
+
+#[test]
+fn test_isolated_shortfall_is_bad_debt() {
+    let mut env = Env::new();
+    let alice = env.open_in(dec!(300), dec!(199), true);
+    let holdings: Option<(Decimal, Decimal)> = env
+        .call("ecdp_isolated", manifest_args!(alice.clone()))
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(holdings, Some((dec!(0), dec!(300))));
+
+    // 5% a window, under the quarantine and the breaker, down to ~0.63
+    // where even all of it, less the 1% tip, is short of the debt
+    let mut price = dec!(1);
+    for _ in 0..9 {
+        env.advance(6);
+        price = price * dec!("0.95");
+        env.post_oracle(price).expect_commit_success();
+    }
+
+    // the pooled ECDPs take over what it's worth, the rest is bad debt
+    let receipt = env.liquidate(&alice);
+    assert!(env.emitted(&receipt, "EcdpLiquidatedEvent"));
+    let bad_debt: Decimal = env
+        .call("get_bad_debt", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_near(bad_debt, dec!(199) - dec!(300) * price * dec!("0.99"));
+
+    // interest pays it off before any of it is minted
+    env.set_borrow_rate(dec!("0.000000001")).expect_commit_success();
+    let (_, _, liabilities, _): (Decimal, Decimal, Decimal, Decimal) = env
+        .call("look_within", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    env.advance(10);
+    env.call("sweep_interest", manifest_args!()).expect_commit_success();
+    let left: Decimal = env
+        .call("get_bad_debt", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_near(left, bad_debt - liabilities * dec!("0.000000001") * dec!(600));
+}
+
+#[test]
+fn test_leverage_round_trip() {
+    let mut env = Env::with_eux();
+    let alice = env.open(dec!(100), dec!(20));
+
+    // up to 300%, the ~3% the pool takes is under the 5% allowed
+    let manifest = env
+        .prove(ManifestBuilder::new(), &alice, "ecdp")
+        .call_function_with_name_lookup(env.package, "Usd", "leverage", |lookup| {
+            (env.usd, lookup.proof("ecdp"), dec!(3), dec!("0.05"), None::<Instant>)
+        })
+        .build();
+    env.run(manifest).expect_commit_success();
+    let (assets, debt) = env.look(&alice);
+    assert!(assets / debt >= dec!(3) && assets / debt < dec!("3.1"));
+
+    // and back down to 500%, what's left of the swap comes back
+    let eusd_before = env.balance(env.eusd);
+    let manifest = env
+        .prove(ManifestBuilder::new(), &alice, "ecdp")
+        .call_function_with_name_lookup(env.package, "Usd", "deleverage", |lookup| {
+            (env.usd, lookup.proof("ecdp"), dec!(5), dec!("0.05"), None::<Instant>)
+        })
+        .deposit_batch(env.account)
+        .build();
+    env.run(manifest).expect_commit_success();
+    let (assets, debt) = env.look(&alice);
+    assert!(assets / debt >= dec!(5) && assets / debt < dec!("5.5"));
+    assert!(env.balance(env.eusd) > eusd_before);
+
+    // a slippage bound the pool can't meet fails the whole loop
+    let manifest = env
+        .prove(ManifestBuilder::new(), &alice, "ecdp")
+        .call_function_with_name_lookup(env.package, "Usd", "leverage", |lookup| {
+            (env.usd, lookup.proof("ecdp"), dec!(2), dec!("0.001"), None::<Instant>)
+        })
+        .build();
+    env.run(manifest).expect_commit_failure();
+}
+
+#[test]
+fn test_close_refunds_all_collateral_and_burns_the_badge() {
+    let mut env = Env::new();
+    let alice = env.open_in(dec!(300), dec!(100), true);
+    let deposit = env.deposit_of(&alice);
+
+    // short of the debt
+    env.close(&alice, dec!(99)).expect_commit_failure();
+
+    // all 300 XRD back, the deposit with them, and what's over the debt
+    let xrd_before = env.balance(XRD);
+    let eusd_before = env.balance(env.eusd);
+    env.close(&alice, dec!(110)).expect_commit_success();
+    assert_eq!(env.balance(XRD) - xrd_before, dec!(300) + deposit);
+    assert_near(eusd_before - env.balance(env.eusd), dec!(100));
+
+    // only the first ECDP's badge is left
+    assert_eq!(env.balance(env.ecdp), dec!(1));
+    env.call("ecdp_look", manifest_args!(alice)).expect_commit_failure();
+}
+
+#[test]
+fn test_protection_repays_and_boosts() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(300), dec!(150));
+    let bob = env.open(dec!(1000), dec!(100));
+    let eusd = env.eusd;
+
+    let protect = |env: &mut Env, id: &NonFungibleLocalId, rule: ProtectionRule| {
+        let manifest = env
+            .prove(ManifestBuilder::new(), id, "ecdp")
+            .call_method_with_name_lookup(env.usd, "ecdp_set_protection", |lookup| {
+                (Some(rule), lookup.proof("ecdp"))
+            })
+            .build();
+        env.run(manifest).expect_commit_success();
+    };
+    let execute = |env: &mut Env, id: &NonFungibleLocalId, resource: ResourceAddress, amount: Decimal| {
+        let manifest = ManifestBuilder::new()
+            .withdraw_from_account(env.account, resource, amount)
+            .take_all_from_worktop(resource, "funds")
+            .call_method_with_name_lookup(env.usd, "execute_protection", |lookup| {
+                (id.clone(), lookup.bucket("funds"))
+            })
+            .deposit_batch(env.account)
+            .build();
+        env.run(manifest)
+    };
+
+    // alice at 200%, under 250% a fifth of her 300 is sold, the 1% fee off the repay
+    protect(&mut env, &alice, ProtectionRule { repay_under: Some((dec!("2.5"), dec!("0.2"))), boost_over: None });
+    let receipt = execute(&mut env, &alice, eusd, dec!(100));
+    assert!(env.emitted(&receipt, "ProtectionEvent"));
+    let (assets, debt) = env.look(&alice);
+    assert_near(assets, dec!(240));
+    assert_near(debt, dec!(150) - dec!("59.4"));
+
+    // back over the trigger, nothing to do
+    execute(&mut env, &alice, eusd, dec!(100)).expect_commit_failure();
+
+    // bob at 1000%, over 800% 100 XRD goes in for 101 EUSD minted to the executor
+    protect(&mut env, &bob, ProtectionRule { repay_under: None, boost_over: Some((dec!(8), dec!(5))) });
+    let eusd_before = env.balance(eusd);
+    execute(&mut env, &bob, XRD, dec!(100)).expect_commit_success();
+    assert_near(env.balance(eusd) - eusd_before, dec!(101));
+    let (assets, debt) = env.look(&bob);
+    assert_near(assets, dec!(1100));
+    assert_near(debt, dec!(201));
+}
+
+#[test]
+fn test_twap_over_a_window_ignores_pushes_within_it() {
+    let mut env = Env::with_eux();
+    let eusd = env.eusd;
+
+    // not a window old yet
+    let twap: Option<Decimal> = env
+        .eux_call("twap", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(twap, None);
+
+    // a window at 1, a small swap is the first observation past it
+    env.advance(31);
+    env.post_oracle(dec!(1)).expect_commit_success();
+    env.eux_swap(eusd, dec!(1)).expect_commit_success();
+    let twap: Option<Decimal> = env
+        .eux_call("twap", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    let twap = twap.expect("no twap");
+    assert_near(twap, dec!(1));
+
+    // a ~15% push doesn't count until time passes over it
+    // that far off the TWAP the AA sits out, so it doesn't undo it either
+    env.eux_swap(eusd, dec!(60)).expect_commit_success();
+    let pushed: Option<Decimal> = env
+        .eux_call("twap", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(pushed, Some(twap));
+    let spot: Decimal = env
+        .eux_call("spot_price", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert!(spot < dec!("0.86"));
+
+    // held for a few minutes, it starts to
+    env.advance(5);
+    let later: Option<Decimal> = env
+        .eux_call("twap", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert!(later.expect("no twap") < twap);
+}
+
+#[test]
+fn test_oracle_sources_cross_checked() {
+    let mut env = Env::with_eux();
+    let eusd = env.eusd;
+    let alice = env.open(dec!(1000), dec!(100));
+
+    // Eux pushes a TWAP of 1 on its first observation a window in
+    env.advance(31);
+    env.post_oracle(dec!(1)).expect_commit_success();
+    env.eux_swap(eusd, dec!(1)).expect_commit_success();
+
+    // the TWAP alone is a price
+    env.azero(
+        "set_oracle_sources",
+        manifest_args!(vec![OracleSource::EuxTwap], dec!("0.01")),
+    )
+    .expect_commit_success();
+    let price: Option<Decimal> = env
+        .call("guarded_get_oracle", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_near(price.expect("no price"), dec!(1));
+
+    // the feeders first, checked against the TWAP, 4% apart is past the 1%
+    env.azero(
+        "set_oracle_sources",
+        manifest_args!(vec![OracleSource::Feeders, OracleSource::EuxTwap], dec!("0.01")),
+    )
+    .expect_commit_success();
+    env.post_oracle(dec!("1.04")).expect_commit_success();
+    let price: Option<Decimal> = env
+        .call("guarded_get_oracle", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(price, None);
+    env.mint(&alice, dec!(10)).expect_commit_failure();
+
+    // within 5% it's the feeders' price
+    env.azero(
+        "set_oracle_sources",
+        manifest_args!(vec![OracleSource::Feeders, OracleSource::EuxTwap], dec!("0.05")),
+    )
+    .expect_commit_success();
+    let price: Option<Decimal> = env
+        .call("guarded_get_oracle", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(price, Some(dec!("1.04")));
+    env.mint(&alice, dec!(10)).expect_commit_success();
+}
+
+#[test]
+fn test_oracle_bounds_clamp_and_quarantine() {
+    let mut env = Env::new();
+    let oracle2 = env.oracle2;
+
+    // nonpositive or past the absolute bounds
+    env.post_oracle(dec!(0)).expect_commit_failure();
+    env.post_oracle(dec!(1000)).expect_commit_failure();
+
+    // 20% up is clamped to the 10% max deviation, and that is still past the 5% quarantine
+    let receipt = env.post_oracle(dec!("1.2"));
+    assert!(env.emitted(&receipt, "OracleQuarantineEvent"));
+    let (price, _): (Decimal, Instant) = env
+        .call("get_oracle", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(price, dec!(1));
+
+    // the same feeder can't confirm itself
+    let receipt = env.post_oracle(dec!("1.2"));
+    assert!(env.emitted(&receipt, "OracleQuarantineEvent"));
+
+    // a second one can, the clamped price goes through
+    env.post_oracle_as(oracle2, dec!("1.2")).expect_commit_success();
+    let (price, _): (Decimal, Instant) = env
+        .call("get_oracle", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(price, dec!("1.1"));
+}
+
+#[test]
+fn test_unstake_keeps_the_buffer_and_claims_once_matured() {
+    let mut env = Env::new();
+    let exrd = env.exrd;
+
+    // the first ECDP's 4000 EXRD are all of the assets, so 10% of it has to stay liquid
+    let buffer: Decimal = env
+        .call("get_unstake_buffer", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(buffer, dec!("0.1"));
+
+    env.call("unstake_exrd", manifest_args!(dec!(100))).expect_commit_failure();
+    env.azero("unstake_exrd", manifest_args!(dec!(3700))).expect_commit_failure();
+    env.azero("unstake_exrd", manifest_args!(dec!(3500))).expect_commit_success();
+
+    let (unstaking, unstakes): (Decimal, Vec<(NonFungibleLocalId, Epoch, Decimal)>) = env
+        .call("unstaking_look", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(unstakes.len(), 1);
+    assert_eq!(unstaking, unstakes[0].2);
+
+    // what's unstaking still backs the first ECDP
+    let first = env.first.clone();
+    let (assets, _) = env.look(&first);
+    assert_near(assets, dec!(4000));
+
+    // nothing matured yet
+    let claimed: Decimal = env
+        .call("claim_unstaked", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(claimed, dec!(0));
+
+    env.runner.set_current_epoch(unstakes[0].1);
+    let claimed: Decimal = env
+        .call("claim_unstaked", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(claimed, unstaking);
+
+    let (unstaking, unstakes): (Decimal, Vec<(NonFungibleLocalId, Epoch, Decimal)>) = env
+        .call("unstaking_look", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(unstaking, dec!(0));
+    assert!(unstakes.is_empty());
+    assert_eq!(env.balance(exrd), dec!(700));
+}
+
+#[test]
+fn test_exrd_rate_cached_and_max_drop_bounded() {
+    let mut env = Env::new();
+
+    env.call("set_exrd_max_drop", manifest_args!(dec!("0.05"))).expect_commit_failure();
+    env.azero("set_exrd_max_drop", manifest_args!(dec!(1))).expect_commit_failure();
+    env.azero("set_exrd_max_drop", manifest_args!(dec!(0))).expect_commit_success();
+
+    // a fresh validator redeems 1:1
+    let rate: Decimal = env
+        .call("exrdxrd", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(rate, dec!(1));
+
+    // a new epoch refreshes the rate, and an unchanged one isn't a drop even at no tolerance
+    env.runner.set_current_epoch(Epoch::of(100));
+    env.post_oracle(dec!(1)).expect_commit_success();
+    let first = env.first.clone();
+    let (assets, _) = env.look(&first);
+    assert_near(assets, dec!(4000));
+
+    let (tripped, _): (bool, (Decimal, Decimal, Decimal)) = env
+        .call("get_breaker", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert!(!tripped);
+}
+
+#[test]
+fn test_eux_quotes_match_the_swap() {
+    let mut env = Env::with_eux();
+    let (eusd, exrd) = (env.eusd, env.exrd);
+
+    // small enough to stay in the AA's bounds, so the quote is the plain curve
+    let quote: Decimal = env
+        .eux_call("sim_swap", manifest_args!(dec!(2), eusd))
+        .expect_commit_success()
+        .output(0);
+    let before = env.balance(exrd);
+    env.eux_swap(eusd, dec!(2)).expect_commit_success();
+    assert_eq!(env.balance(exrd) - before, quote);
+
+    // and back, what it takes to get exactly one out
+    let input: Decimal = env
+        .eux_call("in_given_out", manifest_args!(dec!(1), eusd))
+        .expect_commit_success()
+        .output(0);
+    let before = env.balance(exrd);
+    env.eux_swap(eusd, input).expect_commit_success();
+    assert_near(env.balance(exrd) - before, dec!(1));
+
+    // not more than there is
+    env.eux_call("in_given_out", manifest_args!(dec!(700), eusd))
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_eux_guarded_swap_min_out_and_deadline() {
+    let mut env = Env::with_eux();
+    let (account, eusd) = (env.account, env.eusd);
+
+    let quote: Decimal = env
+        .eux_call("sim_swap", manifest_args!(dec!(2), eusd))
+        .expect_commit_success()
+        .output(0);
+    let now = Instant::new(env.now_ms / 1000);
+    let past = now.add_minutes(-2).unwrap();
+    let later = now.add_minutes(2).unwrap();
+
+    let guarded = |env: &mut Env, min_out: Decimal, deadline: Option<Instant>| {
+        let manifest = ManifestBuilder::new()
+            .withdraw_from_account(account, eusd, dec!(2))
+            .take_all_from_worktop(eusd, "input")
+            .call_method_with_name_lookup(env.eux, "guarded_swap", |lookup| {
+                (lookup.bucket("input"), min_out, deadline)
+            })
+            .deposit_batch(account)
+            .build();
+        env.run(manifest)
+    };
+
+    guarded(&mut env, quote + dec!("0.000001"), None).expect_commit_failure();
+    guarded(&mut env, quote, Some(past)).expect_commit_failure();
+    guarded(&mut env, quote, Some(later)).expect_commit_success();
 }
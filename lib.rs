@@ -0,0 +1,32 @@
+
+#[test]
+fn test_recovery_mode_gates_draws_and_liquidates_at_bp() {
+    let mut env = Env::new();
+    let alice = env.open(dec!(1000), dec!(100));
+    let bob = env.open(dec!(500), dec!(100));
+
+    // 5500 against 977 is ~563%, under a backstop of 600%
+    let mode: (bool, bool) = env
+        .call("recovery_mode", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(mode, (false, false));
+    env.set_mcr(dec!("1.5"), dec!(6)).expect_commit_success();
+    let mode: (bool, bool) = env
+        .call("recovery_mode", manifest_args!())
+        .expect_commit_success()
+        .output(0);
+    assert_eq!(mode, (true, false));
+
+    // nothing that lowers the TCR, alice at 1000% or not
+    env.mint(&alice, dec!(10)).expect_commit_failure();
+    env.collateralize(&alice, XRD, dec!(100)).expect_commit_success();
+
+    // bob at 500% is over mcr, but liquidatable under bp
+    let receipt = env.liquidate(&bob);
+    assert!(env.emitted(&receipt, "EcdpLiquidatedEvent"));
+
+    // back out of it, draws go through again
+    env.set_mcr(dec!("1.5"), dec!("1.7")).expect_commit_success();
+    env.mint(&alice, dec!(10)).expect_commit_success();
+}